@@ -1,11 +1,12 @@
 use crate::config::Context;
-use crate::{trust, util, AppId, Verbs};
+use crate::http::{self, Response};
+use crate::util::Output;
+use crate::{cache, history, openid, trust, util, watch, AppId, Member_roles, Roles, Verbs};
 use anyhow::{anyhow, Context as AnyhowContext, Result};
-use oauth2::TokenResponse;
-use reqwest::blocking::{Client, Response};
 use reqwest::{StatusCode, Url};
 use serde_json::{from_str, json, Value};
 use std::process::exit;
+use std::time::Duration;
 use tabular::{Row, Table};
 
 fn craft_url(base: &Url, app_id: Option<&str>) -> String {
@@ -21,9 +22,8 @@ pub fn create(
     app: AppId,
     data: serde_json::Value,
     file: Option<&str>,
+    id_only: bool,
 ) -> Result<()> {
-    let client = Client::new();
-    let url = craft_url(&config.registry_url, None);
     let body = match file {
         Some(f) => util::get_data_from_file(f)?,
         None => {
@@ -36,45 +36,102 @@ pub fn create(
         }
     };
 
-    client
+    let res = post(config, &body)?;
+    if id_only {
+        util::print_id(res, &body, &app);
+    } else {
+        util::print_result(res, format!("App {}", app), Verbs::create);
+    }
+    Ok(())
+}
+
+// Create an app from the whole resource.
+pub fn post(config: &Context, body: &Value) -> Result<Response> {
+    let client = http::client();
+    let url = craft_url(&config.registry_url, None);
+
+    let req = client
         .post(&url)
         .header(reqwest::header::CONTENT_TYPE, "application/json")
-        .body(body.to_string())
-        .bearer_auth(&config.token.access_token().secret())
-        .send()
-        .context("Can't create app.")
-        .map(|res| util::print_result(res, format!("App {}", app), Verbs::create))
+        .body(body.to_string());
+
+    http::send(config, req).context("Can't create app.")
+}
+
+pub fn read(config: &Context, app: AppId, include_deleted: bool, output: &Output) -> Result<()> {
+    let client = http::client();
+    let url = craft_url(&config.registry_url, Some(&app));
+    let req = client
+        .get(&url)
+        .query(&util::list_query(None, include_deleted));
+    let res = http::send(config, req).context("Can't retrieve app data.")?;
+
+    if let (StatusCode::OK, Ok(resource)) = (res.status(), res.json::<Value>()) {
+        util::deletion_notice(&format!("App {}", app), &resource);
+    }
+    util::print_body(res, output)?;
+
+    // only shown to humans, it would break other outputs.
+    if output.is_default() && !http::is_offline() {
+        if let Some(user) = pending_transfer(config, &app) {
+            eprintln!(
+                "The ownership of app {} is being transferred to {}.",
+                app, user
+            );
+        }
+    }
+    Ok(())
+}
+
+pub fn watch(config: &Context, app: AppId, interval: Duration, status_only: bool) -> Result<()> {
+    let url = craft_url(&config.registry_url, Some(&app));
+    watch::watch(config, &url, interval, status_only)
 }
 
-pub fn read(config: &Context, app: AppId) -> Result<()> {
-    get(config, &app).map(|res| util::print_result(res, app.to_string(), Verbs::get))
+pub fn history(config: &Context, app: AppId) -> Result<()> {
+    history::list(config, &craft_url(&config.registry_url, Some(&app)))
 }
 
-pub fn delete(config: &Context, app: AppId, ignore_missing: bool) -> Result<()> {
-    let client = Client::new();
+pub fn read_revision(config: &Context, app: AppId, revision: u64, output: &Output) -> Result<()> {
     let url = craft_url(&config.registry_url, Some(&app));
+    history::read_revision(config, &url, revision, output)
+}
 
-    client
-        .delete(&url)
-        .bearer_auth(&config.token.access_token().secret())
-        .send()
-        .context("Can't get app.")
-        .map(|res| {
-            if ignore_missing && res.status() == StatusCode::NOT_FOUND {
-                exit(0);
-            } else {
-                util::print_result(res, format!("App {}", &app), Verbs::delete)
-            }
-        })
+pub fn delete(config: &Context, app: AppId, ignore_missing: bool, force: bool) -> Result<()> {
+    let client = http::client();
+    let url = craft_url(&config.registry_url, Some(&app));
+
+    let res = http::send(config, client.delete(&url)).context("Can't delete app.")?;
+    if ignore_missing && res.status() == StatusCode::NOT_FOUND {
+        exit(0);
+    }
+    util::print_result(res, format!("App {}", &app), Verbs::delete);
+
+    util::finalize_deletion(config, &url, &format!("App {}", &app), force)
 }
 
-pub fn edit(config: &Context, app: AppId, file: Option<&str>) -> Result<()> {
+pub fn edit(
+    config: &Context,
+    app: AppId,
+    file: Option<&str>,
+    resource_version: Option<&str>,
+    path: Option<&str>,
+    yes: bool,
+) -> Result<()> {
     match file {
         Some(f) => {
-            let data = util::get_data_from_file(f)?;
+            let mut data = util::get_data_from_file(f)?;
+            if let Some(version) = resource_version {
+                util::set_resource_version(&mut data, version)?;
+            }
 
-            put(&config, &app, data)
-                .map(|res| util::print_result(res, format!("App {}", &app), Verbs::edit))
+            let res = put(&config, &app, data)?;
+            if res.status() == StatusCode::CONFLICT {
+                let current = get(config, &app)?.json()?;
+                return Err(util::conflict_error(format!("App {}", &app), &current));
+            }
+            util::print_result(res, format!("App {}", &app), Verbs::edit);
+            Ok(())
         }
         None => {
             //read app data
@@ -83,7 +140,7 @@ pub fn edit(config: &Context, app: AppId, file: Option<&str>) -> Result<()> {
                 Ok(r) => match r.status() {
                     StatusCode::OK => {
                         let body = r.text().unwrap_or_else(|_| "{}".to_string());
-                        let insert = util::editor(body)?;
+                        let insert = util::editor(body, path, yes)?;
 
                         put(config, &app, insert)
                             .map(|p| util::print_result(p, format!("App {}", &app), Verbs::edit))
@@ -102,26 +159,78 @@ pub fn edit(config: &Context, app: AppId, file: Option<&str>) -> Result<()> {
     }
 }
 
-pub fn list(config: &Context, labels: Option<String>) -> Result<()> {
-    let client = Client::new();
-    let url = craft_url(&config.registry_url, None);
+pub fn list(
+    config: &Context,
+    labels: Option<String>,
+    include_deleted: bool,
+    role: Option<Roles>,
+    output: &Output,
+) -> Result<()> {
+    let user = role.map(|_| openid::user_id(config)).transpose()?;
+    let filter = |apps: Vec<Value>| match (role, &user) {
+        (Some(role), Some(user)) => filter_role(config, apps, role, user),
+        _ => Ok(apps),
+    };
 
-    let mut req = client
-        .get(&url)
-        .bearer_auth(&config.token.access_token().secret());
+    if output.is_streamed() {
+        let url = craft_url(&config.registry_url, None);
+        let query = util::list_query(labels, include_deleted);
+        return util::list_paginated(config, &url, &query, output.chunk_size, |page| {
+            output.list(&filter(output.filter(page))?)
+        });
+    }
 
-    if let Some(labels) = labels {
-        req = req.query(&[("labels", labels)]);
+    let apps = match output.chunk_size {
+        Some(size) => {
+            let url = craft_url(&config.registry_url, None);
+            let query = util::list_query(labels, include_deleted);
+            util::list_all(config, &url, &query, Some(size))?
+        }
+        None => get_list(config, labels, include_deleted)?,
+    };
+    let mut apps = filter(output.filter(apps))?;
+    output.sort(&mut apps);
+    if output.is_default() {
+        pretty_list(apps, include_deleted, output.full_timestamps)
+    } else {
+        output.list(&apps)
     }
+}
 
-    let res = req.send().context("Can't list apps");
+// The names of the apps, reused for a few seconds.
+pub fn names(config: &Context) -> Result<Vec<String>> {
+    let key = craft_url(&config.registry_url, None);
+    if let Some(names) = cache::load_names(&key) {
+        return Ok(names);
+    }
+
+    let names: Vec<String> = get_list(config, None, false)?
+        .iter()
+        .map(util::resource_name)
+        .collect();
+    if let Err(e) = cache::store_names(&key, &names) {
+        log::debug!("Could not cache the app names : {}", e);
+    }
+    Ok(names)
+}
+
+pub fn get_list(
+    config: &Context,
+    labels: Option<String>,
+    include_deleted: bool,
+) -> Result<Vec<Value>> {
+    let client = http::client();
+    let url = craft_url(&config.registry_url, None);
+
+    let req = client
+        .get(&url)
+        .query(&util::list_query(labels, include_deleted));
+
+    let res = http::send(config, req).context("Can't list apps");
 
     if let Ok(r) = res {
         match r.status() {
-            StatusCode::OK => {
-                pretty_list(r.text()?)?;
-                Ok(())
-            }
+            StatusCode::OK => from_str(r.text()?.as_str()).context("Invalid apps list."),
             e => {
                 log::error!("List operation failed with {}", r.status());
                 util::exit_with_code(e)
@@ -132,14 +241,201 @@ pub fn list(config: &Context, labels: Option<String>) -> Result<()> {
     }
 }
 
+fn filter_role(config: &Context, apps: Vec<Value>, role: Roles, user: &str) -> Result<Vec<Value>> {
+    let mut filtered = Vec::new();
+    for app in apps {
+        let name = app["metadata"]["name"].as_str().unwrap_or_default();
+        if user_role(config, name, user)? == role {
+            filtered.push(app);
+        }
+    }
+    Ok(filtered)
+}
+
+fn members_url(config: &Context, app: &str) -> String {
+    format!(
+        "{}{}/apps/{}/members",
+        config.registry_url,
+        util::ADMIN_API_PATH,
+        app
+    )
+}
+
+// The members of an app can only be read by its owner and its admins. The owner is not part of them.
+// Other roles, such as manager or reader, are shown as member.
+fn user_role(config: &Context, app: &str, user: &str) -> Result<Roles> {
+    let url = members_url(config, app);
+    let res = http::send(config, http::client().get(&url))
+        .context(format!("Can't read the members of app {}", app))?;
+
+    match res.status() {
+        StatusCode::OK => {
+            let members: Value = res.json()?;
+            Ok(match members["members"][user]["role"].as_str() {
+                Some("admin") => Roles::admin,
+                Some(_) => Roles::member,
+                None => Roles::owner,
+            })
+        }
+        StatusCode::FORBIDDEN | StatusCode::NOT_FOUND => Ok(Roles::member),
+        s => Err(anyhow!(
+            "Reading the members of app {} failed with {}",
+            app,
+            s
+        )),
+    }
+}
+
+pub fn set_member(config: &Context, app: &str, user: &str, role: Member_roles) -> Result<()> {
+    let mut members = get_members(config, app)?;
+    members["members"][user] = json!({ "role": role.as_ref() });
+
+    put_members(config, app, &members)?;
+    println!("{} is now {} of app {}.", user, role.as_ref(), app);
+    Ok(())
+}
+
+pub fn remove_member(config: &Context, app: &str, user: &str, yes: bool) -> Result<()> {
+    let mut members = get_members(config, app)?;
+    let removed = members["members"]
+        .as_object_mut()
+        .and_then(|m| m.remove(user))
+        .ok_or_else(|| anyhow!("{} is not a member of app {}.", user, app))?;
+
+    let admins_left = members["members"]
+        .as_object()
+        .map(|m| m.values().filter(|m| m["role"] == "admin").count())
+        .unwrap_or_default();
+    if removed["role"] == "admin"
+        && admins_left == 0
+        && !yes
+        && user == openid::user_id(config)?
+        && !util::confirm(&format!(
+            "You are the last admin of app {} and may lose access to it. Remove yourself ?",
+            app
+        ))?
+    {
+        return Err(anyhow!(
+            "Removal cancelled. Use --yes to skip the confirmation."
+        ));
+    }
+
+    put_members(config, app, &members)?;
+    println!("{} removed from the members of app {}.", user, app);
+    Ok(())
+}
+
+fn transfer_url(config: &Context, app: &str, action: &str) -> String {
+    format!(
+        "{}{}/apps/{}/{}",
+        config.registry_url,
+        util::ADMIN_API_PATH,
+        app,
+        action
+    )
+}
+
+pub fn transfer(config: &Context, app: &str, user: &str) -> Result<()> {
+    let req = http::client()
+        .put(&transfer_url(config, app, "transfer-ownership"))
+        .json(&json!({ "newUser": user }));
+    let res = http::send(config, req).context(format!("Can't transfer app {}", app))?;
+
+    match res.status() {
+        StatusCode::OK | StatusCode::NO_CONTENT | StatusCode::ACCEPTED => {
+            println!(
+                "The transfer of app {} to {} is started, {} has to accept it with `drg accept app {}`.",
+                app, user, user, app
+            );
+            Ok(())
+        }
+        s => Err(anyhow!("Transferring app {} failed with {}", app, s)),
+    }
+}
+
+pub fn accept(config: &Context, app: &str) -> Result<()> {
+    let req = http::client().put(&transfer_url(config, app, "accept-ownership"));
+    let res = http::send(config, req).context(format!("Can't accept app {}", app))?;
+
+    match res.status() {
+        StatusCode::OK | StatusCode::NO_CONTENT => {
+            println!("You are now the owner of app {}.", app);
+            Ok(())
+        }
+        StatusCode::NOT_FOUND => Err(anyhow!(
+            "There is no pending transfer of app {} to you.",
+            app
+        )),
+        s => Err(anyhow!("Accepting app {} failed with {}", app, s)),
+    }
+}
+
+// The user an app is being transferred to. Only its owner can read it.
+// Best effort : the app is already shown, so failing to read the transfer must not fail the command.
+fn pending_transfer(config: &Context, app: &str) -> Option<String> {
+    let req = http::client().get(&transfer_url(config, app, "transfer-ownership"));
+    let transfer = http::send(config, req).and_then(|res| match res.status() {
+        StatusCode::OK => Ok(res.json::<Value>()?["newUser"]
+            .as_str()
+            .map(|u| u.to_string())),
+        _ => Ok(None),
+    });
+
+    match transfer {
+        Ok(user) => user,
+        Err(e) => {
+            log::debug!("Could not read the pending transfer of app {} : {}", app, e);
+            None
+        }
+    }
+}
+
+fn get_members(config: &Context, app: &str) -> Result<Value> {
+    let res = http::send(config, http::client().get(&members_url(config, app)))
+        .context(format!("Can't read the members of app {}", app))?;
+
+    match res.status() {
+        StatusCode::OK => res.json(),
+        s => Err(anyhow!(
+            "Reading the members of app {} failed with {}",
+            app,
+            s
+        )),
+    }
+}
+
+// The resource version read along with the members makes the update fail if they were modified since.
+fn put_members(config: &Context, app: &str, members: &Value) -> Result<()> {
+    let req = http::client()
+        .put(&members_url(config, app))
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(members.to_string());
+    let res =
+        http::send(config, req).context(format!("Can't update the members of app {}", app))?;
+
+    match res.status() {
+        StatusCode::OK | StatusCode::NO_CONTENT => Ok(()),
+        StatusCode::CONFLICT => Err(anyhow!(
+            "The members of app {} were modified in the meantime, please try again.",
+            app
+        )),
+        s => Err(anyhow!(
+            "Updating the members of app {} failed with {}",
+            app,
+            s
+        )),
+    }
+}
+
+pub fn exists(config: &Context, app: &str) -> Result<bool> {
+    Ok(get(config, app)?.status() != StatusCode::NOT_FOUND)
+}
+
 fn get(config: &Context, app: &str) -> Result<Response> {
-    let client = Client::new();
+    let client = http::client();
     let url = craft_url(&config.registry_url, Some(app));
-    client
-        .get(&url)
-        .bearer_auth(&config.token.access_token().secret())
-        .send()
-        .context("Can't retrieve app data.")
+
+    http::send(config, client.get(&url)).context("Can't retrieve app data.")
 }
 
 pub fn add_trust_anchor(
@@ -185,8 +481,11 @@ pub fn get_trust_anchor(config: &Context, app: &str) -> Result<String> {
                     app_obj_json["spec"]["trustAnchors"]["anchors"][0]["certificate"].clone();
 
                 if cert == Value::Null {
-                    log::error!("No trust anchor found in this application.");
-                    exit(1);
+                    return Err(anyhow!(
+                        "No trust anchor found in app {}. Create one with `drg trust create {}`.",
+                        app,
+                        app
+                    ));
                 }
 
                 Ok(cert.to_string().replace("\"", ""))
@@ -204,34 +503,44 @@ pub fn get_trust_anchor(config: &Context, app: &str) -> Result<String> {
 }
 
 fn put(config: &Context, app: &str, data: serde_json::Value) -> Result<Response> {
-    let client = Client::new();
+    let client = http::client();
     let url = craft_url(&config.registry_url, Some(app));
 
-    client
+    let req = client
         .put(&url)
         .header(reqwest::header::CONTENT_TYPE, "application/json")
-        .bearer_auth(&config.token.access_token().secret())
-        .body(data.to_string())
-        .send()
-        .context("Can't update app data.")
+        .body(data.to_string());
+
+    http::send(config, req).context("Can't update app data.")
 }
 
 // todo drogue-client and the types would be useful for this
-fn pretty_list(data: String) -> Result<()> {
-    let apps_array: Vec<Value> = from_str(data.as_str())?;
-
-    let mut table = Table::new("{:<} {:<}");
-    table.add_row(Row::new().with_cell("NAME").with_cell("AGE"));
+fn pretty_list(apps_array: Vec<Value>, include_deleted: bool, full_timestamps: bool) -> Result<()> {
+    let mut table = Table::new(if include_deleted {
+        "{:<} {:<} {:<}"
+    } else {
+        "{:<} {:<}"
+    });
+    let mut header =
+        Row::new()
+            .with_cell("NAME")
+            .with_cell(if full_timestamps { "CREATED" } else { "AGE" });
+    if include_deleted {
+        header.add_cell("DELETION");
+    }
+    table.add_row(header);
 
     for app in apps_array {
         let name = app["metadata"]["name"].as_str();
         let creation = app["metadata"]["creationTimestamp"].as_str();
         if let Some(name) = name {
-            table.add_row(
-                Row::new()
-                    .with_cell(name)
-                    .with_cell(util::age(creation.unwrap())?),
-            );
+            let mut row = Row::new()
+                .with_cell(name)
+                .with_cell(util::timestamp(creation.unwrap(), full_timestamps)?);
+            if include_deleted {
+                row.add_cell(util::deletion_age(&app)?);
+            }
+            table.add_row(row);
         }
     }
 
@@ -1,9 +1,11 @@
-use crate::{trust, util, AppId};
+use crate::util::Output;
+use crate::{apps, http, trust, util, AppId};
 
-use crate::config::Context;
+use crate::config::{self, Context};
 use anyhow::{anyhow, Result};
-use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use clap::{App, AppSettings, Arg, ArgMatches, Shell, SubCommand};
 use std::convert::AsRef;
+use std::str::FromStr;
 use strum_macros::{AsRefStr, EnumString};
 
 #[derive(AsRefStr, EnumString)]
@@ -15,6 +17,11 @@ pub enum Verbs {
     get,
     set,
     cmd,
+    rollback,
+    transfer,
+    accept,
+    label,
+    annotate,
 }
 
 #[derive(AsRefStr, EnumString)]
@@ -23,12 +30,18 @@ pub enum Set_targets {
     gateway,
     password,
     alias,
+    member,
 }
 
 #[derive(AsRefStr, EnumString)]
 #[allow(non_camel_case_types)]
 pub enum Set_args {
     username,
+    generate,
+    length,
+    user,
+    role,
+    to,
 }
 
 #[derive(AsRefStr, EnumString)]
@@ -63,6 +76,106 @@ pub enum Parameters {
     #[strum(serialize = "key-input")]
     key_input,
     payload,
+    count,
+    interval,
+    concurrency,
+    #[strum(serialize = "output-file")]
+    output_file,
+    output,
+    #[strum(serialize = "sort-by")]
+    sort_by,
+    #[strum(serialize = "registry-url")]
+    registry_url,
+    #[strum(serialize = "access-token")]
+    access_token,
+    #[strum(serialize = "resource-version")]
+    resource_version,
+    #[strum(serialize = "dump-http")]
+    dump_http,
+    header,
+    revision,
+    #[strum(serialize = "to-revision")]
+    to_revision,
+    #[strum(serialize = "from-file")]
+    from_file,
+    #[strum(serialize = "login-timeout")]
+    login_timeout,
+    scope,
+    audience,
+    #[strum(serialize = "client-id")]
+    client_id,
+    columns,
+    #[strum(serialize = "user-agent")]
+    user_agent,
+    timeout,
+    #[strum(serialize = "connect-timeout")]
+    connect_timeout,
+    #[strum(serialize = "max-time")]
+    max_time,
+    role,
+    password,
+    psk,
+    only,
+    #[strum(serialize = "chunk-size")]
+    chunk_size,
+    #[strum(serialize = "token-file")]
+    token_file,
+    #[strum(serialize = "audit-file")]
+    audit_file,
+    #[strum(serialize = "out-dir")]
+    out_dir,
+    #[strum(serialize = "name-template")]
+    name_template,
+    depth,
+    width,
+    since,
+    until,
+    path,
+    #[strum(serialize = "label-columns")]
+    label_columns,
+    template,
+    #[strum(serialize = "env-prefix")]
+    env_prefix,
+    changes,
+    save,
+    #[strum(serialize = "app-selector")]
+    app_selector,
+    #[strum(serialize = "output-template-file")]
+    output_template_file,
+    #[strum(serialize = "output-template")]
+    output_template,
+}
+
+#[derive(AsRefStr, EnumString, Clone, Copy, PartialEq)]
+#[allow(non_camel_case_types)]
+pub enum Outputs {
+    json,
+    #[strum(serialize = "json-compact")]
+    json_compact,
+    jsonl,
+    name,
+    tsv,
+    wide,
+    id,
+    env,
+    pem,
+}
+
+#[derive(AsRefStr, EnumString, Clone, Copy, PartialEq)]
+#[allow(non_camel_case_types)]
+pub enum Roles {
+    owner,
+    admin,
+    member,
+}
+
+// The roles that can be given to the members of an app.
+#[derive(AsRefStr, EnumString, Clone, Copy, PartialEq)]
+#[allow(non_camel_case_types)]
+pub enum Member_roles {
+    admin,
+    manager,
+    reader,
 }
 
 #[derive(AsRefStr, EnumString)]
@@ -76,6 +189,17 @@ pub enum Other_commands {
     trust,
     stream,
     endpoints,
+    manpage,
+    status,
+    completion,
+    ping,
+    export,
+    tree,
+    schema,
+    explain,
+    doctor,
+    #[strum(serialize = "complete-ids")]
+    complete_ids,
 }
 
 #[derive(AsRefStr, EnumString)]
@@ -92,6 +216,13 @@ pub enum Context_subcommands {
     rename,
     #[strum(serialize = "set-default-algo")]
     set_default_algo,
+    #[strum(serialize = "current-context")]
+    current_context,
+    migrate,
+    set,
+    unset,
+    #[strum(serialize = "add-template")]
+    add_template,
 }
 
 #[derive(AsRefStr, EnumString)]
@@ -108,9 +239,57 @@ pub enum Other_flags {
     cert,
     #[strum(serialize = "ignore-missing")]
     ignore_missing,
+    #[strum(serialize = "all-devices")]
+    all_devices,
+    yes,
+    #[strum(serialize = "remember-app")]
+    remember_app,
+    watch,
+    force,
+    #[strum(serialize = "no-config")]
+    no_config,
+    strict,
+    history,
+    #[strum(serialize = "include-deleted")]
+    include_deleted,
+    connected,
+    #[strum(serialize = "only-connected")]
+    only_connected,
+    cache,
+    offline,
+    install,
+    #[strum(serialize = "check-default-app")]
+    check_default_app,
+    #[strum(serialize = "resolve-aliases")]
+    resolve_aliases,
+    #[strum(serialize = "password-stdin")]
+    password_stdin,
+    curl,
+    #[strum(serialize = "no-env-subst")]
+    no_env_subst,
+    #[strum(serialize = "full-timestamps")]
+    full_timestamps,
+    #[strum(serialize = "decode-psk")]
+    decode_psk,
+    all,
+    #[strum(serialize = "no-verify-token")]
+    no_verify_token,
+    #[strum(serialize = "no-local-config")]
+    no_local_config,
+    trace,
+    #[strum(serialize = "watch-only-status")]
+    watch_only_status,
+    quiet,
+    clipboard,
+    #[strum(serialize = "strict-json")]
+    strict_json,
 }
 
 pub fn parse_arguments() -> ArgMatches<'static> {
+    app().get_matches()
+}
+
+pub fn app() -> App<'static, 'static> {
     let resource_id_arg = Arg::with_name(Parameters::id.as_ref())
         .required(true)
         .help("The unique id of the resource.");
@@ -125,10 +304,58 @@ pub fn parse_arguments() -> ArgMatches<'static> {
 
     let cmd_arg = Arg::with_name(Verbs::cmd.as_ref())
         .required(true)
-        .multiple(true)
-        .number_of_values(2)
-        .value_names(&["command", "device"])
-        .help("Send the <command> to the <device>");
+        .value_name("command")
+        .help("The command to send.");
+
+    let cmd_device_arg = Arg::with_name(Resources::device.as_ref())
+        .required_unless(Other_flags::all_devices.as_ref())
+        .value_name("device")
+        .help("The device to send the command to.");
+
+    let all_devices = Arg::with_name(Other_flags::all_devices.as_ref())
+        .long(Other_flags::all_devices.as_ref())
+        .takes_value(false)
+        .conflicts_with_all(&[Resources::device.as_ref(), Parameters::count.as_ref()])
+        .help("Send the command to every device of the app. Requires --yes.");
+
+    let yes = Arg::with_name(Other_flags::yes.as_ref())
+        .long(Other_flags::yes.as_ref())
+        .short("y")
+        .takes_value(false)
+        .help("Do not ask for confirmation.");
+
+    let concurrency = Arg::with_name(Parameters::concurrency.as_ref())
+        .long(Parameters::concurrency.as_ref())
+        .takes_value(true)
+        .value_name("N")
+        .requires(Other_flags::all_devices.as_ref())
+        .help("Number of commands sent in parallel when using --all-devices. [default: 1]")
+        .validator(|n| match n.parse::<usize>() {
+            Ok(n) if n > 0 => Ok(()),
+            _ => Err(String::from("The value must be a positive integer")),
+        });
+
+    let member_app = Arg::with_name(Parameters::id.as_ref())
+        .required(true)
+        .value_name("app")
+        .help("The app to manage the members of.");
+
+    let member_user = Arg::with_name(Set_args::user.as_ref())
+        .long(Set_args::user.as_ref())
+        .required(true)
+        .takes_value(true)
+        .value_name("USER_ID")
+        .help("The id of the user.");
+
+    let member_role = Arg::with_name(Set_args::role.as_ref())
+        .long(Set_args::role.as_ref())
+        .required(true)
+        .takes_value(true)
+        .value_name("ROLE")
+        .possible_value(Member_roles::admin.as_ref())
+        .possible_value(Member_roles::manager.as_ref())
+        .possible_value(Member_roles::reader.as_ref())
+        .help("The role of the user in the app.");
 
     let url_arg = Arg::with_name(Parameters::url.as_ref())
         .required(true)
@@ -143,9 +370,34 @@ pub fn parse_arguments() -> ArgMatches<'static> {
         .value_name("username")
         .help("The username associated with the password");
 
+    let set_password_arg = Arg::with_name(Verbs::set.as_ref())
+        .required(true)
+        .multiple(true)
+        .min_values(1)
+        .max_values(2)
+        .value_names(&["device", "password"])
+        .help("The device and its new password. The password is omitted when using --generate.");
+
+    let set_password_generate = Arg::with_name(Set_args::generate.as_ref())
+        .long(Set_args::generate.as_ref())
+        .takes_value(false)
+        .help("Generate a random password and print it. It cannot be retrieved later.");
+
+    let set_password_length = Arg::with_name(Set_args::length.as_ref())
+        .long(Set_args::length.as_ref())
+        .takes_value(true)
+        .value_name("LENGTH")
+        .requires(Set_args::generate.as_ref())
+        .help("Length of the generated password. [default: 32]")
+        .validator(|l| match l.parse::<usize>() {
+            Ok(l) if l > 0 => Ok(()),
+            _ => Err("The length must be a positive number".to_string()),
+        });
+
     let app_id_arg = Arg::with_name(Resources::app.as_ref())
         .short("a")
         .long(Resources::app.as_ref())
+        .visible_alias("application")
         .takes_value(true)
         .env("DRG_APP")
         .help("The app owning the device. Can be set with DRG_APP environment variable.");
@@ -155,7 +407,7 @@ pub fn parse_arguments() -> ArgMatches<'static> {
         .short("s")
         .long(Parameters::spec.as_ref())
         .takes_value(true)
-        .help("The spec for the resource. --data is deprecated.");
+        .help("The spec for the resource, @FILE to read it from a file or @URL to fetch it. --data is deprecated.");
 
     let payload_arg = Arg::with_name(Parameters::payload.as_ref())
         .short("p")
@@ -164,6 +416,254 @@ pub fn parse_arguments() -> ArgMatches<'static> {
         .required(false)
         .help("The command body, as a JSON value.");
 
+    let count_arg = Arg::with_name(Parameters::count.as_ref())
+        .long(Parameters::count.as_ref())
+        .alias("repeat")
+        .takes_value(true)
+        .required(false)
+        .value_name("N")
+        .help("Send the command N times. [default: 1]")
+        .validator(|n| match n.parse::<u32>() {
+            Ok(n) if n > 0 => Ok(()),
+            _ => Err(String::from("The value must be a positive integer")),
+        });
+
+    let interval_arg = Arg::with_name(Parameters::interval.as_ref())
+        .long(Parameters::interval.as_ref())
+        .takes_value(true)
+        .required(false)
+        .value_name("DURATION")
+        .requires(Parameters::count.as_ref())
+        .help("Time to wait between two commands when using --count, e.g. 500ms, 1s or 2m.")
+        .validator(|d| {
+            util::parse_duration(&d)
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        });
+
+    let watch = Arg::with_name(Other_flags::watch.as_ref())
+        .long(Other_flags::watch.as_ref())
+        .short("w")
+        .takes_value(false)
+        .requires(Parameters::id.as_ref())
+        .conflicts_with(Parameters::output_file.as_ref())
+        .help("Watch the resource and print it again each time it changes.");
+
+    let watch_only_status = Arg::with_name(Other_flags::watch_only_status.as_ref())
+        .long(Other_flags::watch_only_status.as_ref())
+        .takes_value(false)
+        .requires(Other_flags::watch.as_ref())
+        .help("Only print the status of the resource, when it changes. Changes to the rest of the resource are ignored.");
+
+    let watch_interval = Arg::with_name(Parameters::interval.as_ref())
+        .long(Parameters::interval.as_ref())
+        .takes_value(true)
+        .value_name("DURATION")
+        .requires(Other_flags::watch.as_ref())
+        .help(
+            "Polling interval, when the server does not support watching resources. [default: 2s]",
+        )
+        .validator(|d| {
+            util::parse_duration(&d)
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        });
+
+    let history = Arg::with_name(Other_flags::history.as_ref())
+        .long(Other_flags::history.as_ref())
+        .takes_value(false)
+        .requires(Parameters::id.as_ref())
+        .conflicts_with_all(&[Other_flags::watch.as_ref(), Parameters::revision.as_ref()])
+        .help("List the previous revisions of the resource, if the server keeps them.");
+
+    let revision = Arg::with_name(Parameters::revision.as_ref())
+        .long(Parameters::revision.as_ref())
+        .takes_value(true)
+        .value_name("N")
+        .requires(Parameters::id.as_ref())
+        .conflicts_with(Other_flags::watch.as_ref())
+        .help("Retrieve a previous revision of the resource, if the server keeps them.")
+        .validator(|r| {
+            r.parse::<u64>()
+                .map(|_| ())
+                .map_err(|_| "The revision must be a number".to_string())
+        });
+
+    let to_revision = Arg::with_name(Parameters::to_revision.as_ref())
+        .long(Parameters::to_revision.as_ref())
+        .takes_value(true)
+        .value_name("N")
+        .required_unless(Parameters::from_file.as_ref())
+        .conflicts_with(Parameters::from_file.as_ref())
+        .help("The revision to restore, if the server keeps a history.")
+        .validator(|r| {
+            r.parse::<u64>()
+                .map(|_| ())
+                .map_err(|_| "The revision must be a number".to_string())
+        });
+
+    let from_file = Arg::with_name(Parameters::from_file.as_ref())
+        .long(Parameters::from_file.as_ref())
+        .takes_value(true)
+        .value_name("FILE")
+        .help("Restore the spec from a backup of the device, e.g. saved with get --output-file.");
+
+    let output_file = Arg::with_name(Parameters::output_file.as_ref())
+        .long(Parameters::output_file.as_ref())
+        .takes_value(true)
+        .value_name("FILE")
+        .help("Write the result to FILE instead of the standard output.");
+
+    let clipboard = Arg::with_name(Other_flags::clipboard.as_ref())
+        .long(Other_flags::clipboard.as_ref())
+        .takes_value(false)
+        .conflicts_with_all(&[Parameters::output_file.as_ref(), Parameters::save.as_ref()])
+        .help("Copy the result to the clipboard instead of printing it. It is printed if no clipboard is available.");
+
+    let save = Arg::with_name(Parameters::save.as_ref())
+        .long(Parameters::save.as_ref())
+        .takes_value(true)
+        .value_name("BASENAME")
+        .conflicts_with_all(&[Parameters::output_file.as_ref(), Parameters::output.as_ref()])
+        .help("Write the response to BASENAME, with a .json, .pem or .bin extension depending on its content type.");
+
+    let output_format = Arg::with_name(Parameters::output.as_ref())
+        .short("o")
+        .long(Parameters::output.as_ref())
+        .takes_value(true)
+        .value_name("FORMAT")
+        .possible_value(Outputs::json.as_ref())
+        .possible_value(Outputs::json_compact.as_ref())
+        .possible_value(Outputs::jsonl.as_ref())
+        .possible_value(Outputs::name.as_ref())
+        .possible_value(Outputs::tsv.as_ref())
+        .possible_value(Outputs::wide.as_ref())
+        .possible_value(Outputs::env.as_ref())
+        .possible_value(Outputs::pem.as_ref())
+        .help("Output format. json-compact prints one resource per line, jsonl does the same while fetching lists page by page. name only prints the names. tsv prints tab-separated columns, see --columns. wide prints a table with more columns. env prints shell variable assignments for a single resource. pem prints the certificate a device authenticates with.");
+
+    let output_template_file = Arg::with_name(Parameters::output_template_file.as_ref())
+        .long(Parameters::output_template_file.as_ref())
+        .takes_value(true)
+        .value_name("FILE")
+        .conflicts_with_all(&[Parameters::output.as_ref(), Parameters::only.as_ref()])
+        .help("Render each resource through the template in FILE, see --output-template.");
+
+    let output_template = Arg::with_name(Parameters::output_template.as_ref())
+        .long(Parameters::output_template.as_ref())
+        .takes_value(true)
+        .value_name("TEMPLATE")
+        .conflicts_with_all(&[
+            Parameters::output.as_ref(),
+            Parameters::only.as_ref(),
+            Parameters::output_template_file.as_ref(),
+        ])
+        .help("Render each resource through TEMPLATE, e.g. '{metadata.name} {spec.alias}'. The {...} placeholders are replaced by the dotted fields of the resource, missing fields are left empty.");
+
+    let env_prefix = Arg::with_name(Parameters::env_prefix.as_ref())
+        .long(Parameters::env_prefix.as_ref())
+        .takes_value(true)
+        .value_name("PREFIX")
+        .help("Prefix of the variables printed by -o env. [default: DEVICE or APP]");
+
+    let columns = Arg::with_name(Parameters::columns.as_ref())
+        .long(Parameters::columns.as_ref())
+        .takes_value(true)
+        .use_delimiter(true)
+        .value_name("FIELDS")
+        .help("Comma separated fields to print as columns of the table or tsv output, or as variables of the env output, as dotted paths. Missing fields are left empty. [default for tsv: metadata.name,metadata.creationTimestamp]");
+
+    let label_columns = Arg::with_name(Parameters::label_columns.as_ref())
+        .long(Parameters::label_columns.as_ref())
+        .short("L")
+        .takes_value(true)
+        .use_delimiter(true)
+        .value_name("LABELS")
+        .help("Comma separated labels to print as extra columns of the table or tsv output, or as extra variables of the env output. Missing labels are left empty.");
+
+    let only = Arg::with_name(Parameters::only.as_ref())
+        .long(Parameters::only.as_ref())
+        .visible_alias("field-output")
+        .takes_value(true)
+        .value_name("FIELD")
+        .conflicts_with_all(&[
+            Parameters::output.as_ref(),
+            Parameters::columns.as_ref(),
+            Parameters::label_columns.as_ref(),
+        ])
+        .help("Only print a field of the resource, given as a dotted path. For lists, the field is printed for each resource, an empty line if it's missing.");
+
+    let resource_version = Arg::with_name(Parameters::resource_version.as_ref())
+        .long(Parameters::resource_version.as_ref())
+        .takes_value(true)
+        .value_name("VERSION")
+        .requires(Parameters::filename.as_ref())
+        .help("The expected resource version. The update is rejected if the resource was modified since.");
+
+    let include_deleted = Arg::with_name(Other_flags::include_deleted.as_ref())
+        .long(Other_flags::include_deleted.as_ref())
+        .takes_value(false)
+        .help("Include the resources pending deletion, and show since when they are deleted.");
+
+    let full_timestamps = Arg::with_name(Other_flags::full_timestamps.as_ref())
+        .long(Other_flags::full_timestamps.as_ref())
+        .takes_value(false)
+        .help("Show the creation time of the resources in tables instead of their age.");
+
+    let delete_force = Arg::with_name(Other_flags::force.as_ref())
+        .long(Other_flags::force.as_ref())
+        .takes_value(false)
+        .requires(Other_flags::yes.as_ref())
+        .help("Remove the finalizers of the resource to force its removal. This can leave orphaned resources, requires --yes.");
+
+    let role = Arg::with_name(Parameters::role.as_ref())
+        .long(Parameters::role.as_ref())
+        .takes_value(true)
+        .value_name("ROLE")
+        .possible_value(Roles::owner.as_ref())
+        .possible_value(Roles::admin.as_ref())
+        .possible_value(Roles::member.as_ref())
+        .help("Only list the apps where you have this role. This makes a request per app.");
+
+    let sort_by = Arg::with_name(Parameters::sort_by.as_ref())
+        .long(Parameters::sort_by.as_ref())
+        .takes_value(true)
+        .value_name("FIELD")
+        .help("Sort the list by a field, given as a dotted path. e.g. metadata.creationTimestamp");
+
+    let edit_path = Arg::with_name(Parameters::path.as_ref())
+        .long(Parameters::path.as_ref())
+        .takes_value(true)
+        .value_name("FIELD")
+        .conflicts_with(Parameters::filename.as_ref())
+        .help(
+            "Only edit this field of the resource, given as a dotted path, e.g. spec.credentials",
+        );
+
+    let since = Arg::with_name(Parameters::since.as_ref())
+        .long(Parameters::since.as_ref())
+        .takes_value(true)
+        .value_name("TIME")
+        .help("Only list the resources created since TIME, a timestamp or a duration such as 24h or 7d.")
+        .validator(|t| util::parse_time(&t).map(|_| ()).map_err(|e| format!("{:#}", e)));
+
+    let until = Arg::with_name(Parameters::until.as_ref())
+        .long(Parameters::until.as_ref())
+        .takes_value(true)
+        .value_name("TIME")
+        .help("Only list the resources created until TIME, a timestamp or a duration such as 24h or 7d.")
+        .validator(|t| util::parse_time(&t).map(|_| ()).map_err(|e| format!("{:#}", e)));
+
+    let chunk_size = Arg::with_name(Parameters::chunk_size.as_ref())
+        .long(Parameters::chunk_size.as_ref())
+        .takes_value(true)
+        .value_name("N")
+        .help("Fetch the list N resources per request. [default: the server's page size]")
+        .validator(|n| match n.parse::<usize>() {
+            Ok(n) if n > 0 => Ok(()),
+            _ => Err(String::from("The value must be a positive integer")),
+        });
+
     let file_arg = Arg::with_name(Parameters::filename.as_ref())
         .short("f")
         .long(Parameters::filename.as_ref())
@@ -187,7 +687,7 @@ pub fn parse_arguments() -> ArgMatches<'static> {
         .takes_value(true)
         .global(true)
         .value_name("FILE")
-        .help("Path to the drgconfig file. If not specified, reads $DRGCFG environment variable or defaults to XDG config directory for drg_config.json");
+        .help("Path to the drgconfig file. If not specified, reads $DRGCFG environment variable or defaults to XDG config directory for drg_config.yaml (or drg_config.json). The .drgconfig.yaml or .drgconfig.json file of the current directory or its parents only sets the default_app of the project, not the config file.");
 
     let verbose = Arg::with_name(Other_flags::verbose.as_ref())
         .short("v")
@@ -196,6 +696,175 @@ pub fn parse_arguments() -> ArgMatches<'static> {
         .global(true)
         .help("Enable verbose output. Multiple occurrences increase verbosity.");
 
+    let strict_json = Arg::with_name(Other_flags::strict_json.as_ref())
+        .long(Other_flags::strict_json.as_ref())
+        .takes_value(false)
+        .global(true)
+        .help("Reject JSON data with duplicate keys, instead of keeping the last value.");
+
+    let quiet = Arg::with_name(Other_flags::quiet.as_ref())
+        .short("q")
+        .long(Other_flags::quiet.as_ref())
+        .takes_value(false)
+        .global(true)
+        .help("Do not print notices, such as the app used by default.");
+
+    let remember_app = Arg::with_name(Other_flags::remember_app.as_ref())
+        .long(Other_flags::remember_app.as_ref())
+        .takes_value(false)
+        .global(true)
+        .help(
+            "Remember the app used for device operations, and use it when --app is not provided.",
+        );
+
+    let check_default_app = Arg::with_name(Other_flags::check_default_app.as_ref())
+        .long(Other_flags::check_default_app.as_ref())
+        .takes_value(false)
+        .global(true)
+        .help("Check that the default app still exists when it is used, with an extra request.");
+
+    let no_config = Arg::with_name(Other_flags::no_config.as_ref())
+        .long(Other_flags::no_config.as_ref())
+        .takes_value(false)
+        .global(true)
+        .conflicts_with(Parameters::config.as_ref())
+        .help("Do not read nor write any configuration file, use --registry-url and --access-token instead.");
+
+    let strict = Arg::with_name(Other_flags::strict.as_ref())
+        .long(Other_flags::strict.as_ref())
+        .takes_value(false)
+        .global(true)
+        .help("Refuse to use a configuration file that other users can access.");
+
+    let cache = Arg::with_name(Other_flags::cache.as_ref())
+        .long(Other_flags::cache.as_ref())
+        .takes_value(false)
+        .global(true)
+        .help(
+            "Save the resources read from drogue cloud, so they can be read with --offline later.",
+        );
+
+    let offline = Arg::with_name(Other_flags::offline.as_ref())
+        .long(Other_flags::offline.as_ref())
+        .takes_value(false)
+        .global(true)
+        .conflicts_with(Other_flags::cache.as_ref())
+        .help("Read resources from the cache filled with --cache instead of drogue cloud. Only works for get commands.");
+
+    let user_agent = Arg::with_name(Parameters::user_agent.as_ref())
+        .long(Parameters::user_agent.as_ref())
+        .takes_value(true)
+        .global(true)
+        .value_name("USER_AGENT")
+        .help("The User-Agent header sent with the requests. [default: drg/<version>]");
+
+    let timeout = Arg::with_name(Parameters::timeout.as_ref())
+        .long(Parameters::timeout.as_ref())
+        .takes_value(true)
+        .global(true)
+        .value_name("DURATION")
+        .help("Give up on a request that is not completed after DURATION, e.g. 2m. 0 disables the timeout. [default: 30s]");
+
+    let connect_timeout = Arg::with_name(Parameters::connect_timeout.as_ref())
+        .long(Parameters::connect_timeout.as_ref())
+        .takes_value(true)
+        .global(true)
+        .value_name("DURATION")
+        .help("Give up on a request when connecting to the server takes longer than DURATION, e.g. 5s. No timeout by default, or with 0.");
+
+    let max_time = Arg::with_name(Parameters::max_time.as_ref())
+        .long(Parameters::max_time.as_ref())
+        .takes_value(true)
+        .global(true)
+        .value_name("DURATION")
+        .help("Abort the whole command after DURATION, e.g. 60s, whatever its progress. Bulk operations print what was done before exiting. 0 disables the limit, which is the default.");
+
+    let curl = Arg::with_name(Other_flags::curl.as_ref())
+        .long(Other_flags::curl.as_ref())
+        .takes_value(false)
+        .global(true)
+        .help(
+            "Print the requests as curl commands on the standard error, with the tokens redacted.",
+        );
+
+    let no_env_subst = Arg::with_name(Other_flags::no_env_subst.as_ref())
+        .long(Other_flags::no_env_subst.as_ref())
+        .takes_value(false)
+        .global(true)
+        .help("Do not expand ${VAR} and $VAR environment variables in data files. Otherwise $$ is a literal $.");
+
+    let no_local_config = Arg::with_name(Other_flags::no_local_config.as_ref())
+        .long(Other_flags::no_local_config.as_ref())
+        .takes_value(false)
+        .global(true)
+        .help("Ignore the project settings of the .drgconfig.yaml and .drgconfig.json files of the current directory and its parents.");
+
+    let trace = Arg::with_name(Other_flags::trace.as_ref())
+        .long(Other_flags::trace.as_ref())
+        .takes_value(false)
+        .global(true)
+        .help("Send a W3C traceparent header with the requests, and print its trace id on the standard error.");
+
+    let no_verify_token = Arg::with_name(Other_flags::no_verify_token.as_ref())
+        .long(Other_flags::no_verify_token.as_ref())
+        .takes_value(false)
+        .global(true)
+        .help("Do not check whether the token expired before running the command, nor refresh it. A token rejected by the server is still refreshed.");
+
+    let dump_http = Arg::with_name(Parameters::dump_http.as_ref())
+        .long(Parameters::dump_http.as_ref())
+        .takes_value(true)
+        .global(true)
+        .value_name("FILE")
+        .help("Append the HTTP requests and responses to FILE, with the tokens and device credentials redacted. Useful to report bugs.");
+
+    let audit_file = Arg::with_name(Parameters::audit_file.as_ref())
+        .long(Parameters::audit_file.as_ref())
+        .takes_value(true)
+        .global(true)
+        .value_name("FILE")
+        .env("DRG_AUDIT_FILE")
+        .help("Append a JSON line to FILE for each request changing a resource, with the time, the user, the resource and the outcome.");
+
+    let header = Arg::with_name(Parameters::header.as_ref())
+        .long(Parameters::header.as_ref())
+        .short("H")
+        .takes_value(true)
+        .multiple(true)
+        .number_of_values(1)
+        .global(true)
+        .value_name("NAME: VALUE")
+        .help("Add a header to the requests. Can be repeated.")
+        .validator(|h| {
+            http::parse_header(&h)
+                .map(|_| ())
+                .map_err(|e| format!("{:#}", e))
+        });
+
+    let registry_url = Arg::with_name(Parameters::registry_url.as_ref())
+        .long(Parameters::registry_url.as_ref())
+        .takes_value(true)
+        .global(true)
+        .value_name("URL")
+        .env("DRG_REGISTRY_URL")
+        .help("The registry url to use with --no-config. Can be set with DRG_REGISTRY_URL environment variable.");
+
+    let access_token = Arg::with_name(Parameters::access_token.as_ref())
+        .long(Parameters::access_token.as_ref())
+        .takes_value(true)
+        .global(true)
+        .value_name("TOKEN")
+        .env("DRG_TOKEN")
+        .hide_env_values(true)
+        .help("The bearer token to use with --no-config. Can be set with DRG_TOKEN environment variable.");
+
+    let token_file = Arg::with_name(Parameters::token_file.as_ref())
+        .long(Parameters::token_file.as_ref())
+        .takes_value(true)
+        .global(true)
+        .value_name("FILE")
+        .help("Read the bearer token from a file, such as a mounted secret, instead of using the token of the context. It takes precedence over --access-token.");
+
     let ignore_missing = Arg::with_name(Other_flags::ignore_missing.as_ref())
         .long(Other_flags::ignore_missing.as_ref())
         .takes_value(false)
@@ -220,6 +889,39 @@ pub fn parse_arguments() -> ArgMatches<'static> {
         .short("k")
         .help("Do not activate the new context.");
 
+    let login_scope = Arg::with_name(Parameters::scope.as_ref())
+        .long(Parameters::scope.as_ref())
+        .takes_value(true)
+        .multiple(true)
+        .number_of_values(1)
+        .value_name("SCOPE")
+        .help(
+            "A scope to request, can be repeated. They replace the default scope: offline_access",
+        );
+
+    let login_audience = Arg::with_name(Parameters::audience.as_ref())
+        .long(Parameters::audience.as_ref())
+        .takes_value(true)
+        .value_name("AUDIENCE")
+        .help("The audience of the requested token.");
+
+    let login_client_id = Arg::with_name(Parameters::client_id.as_ref())
+        .long(Parameters::client_id.as_ref())
+        .takes_value(true)
+        .value_name("CLIENT_ID")
+        .help("The OAuth client to use, when the installation does not use the default drogue client.");
+
+    let login_timeout = Arg::with_name(Parameters::login_timeout.as_ref())
+        .long(Parameters::login_timeout.as_ref())
+        .takes_value(true)
+        .value_name("DURATION")
+        .help("How long to wait for the authentication in the browser. [default: 5m]")
+        .validator(|d| {
+            util::parse_duration(&d)
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        });
+
     let labels = Arg::with_name(&Parameters::labels.as_ref())
         .required(false)
         .short("l")
@@ -228,6 +930,17 @@ pub fn parse_arguments() -> ArgMatches<'static> {
         .multiple(true)
         .help("A comma separated list of the label filters to filter the list with.");
 
+    let label_changes = Arg::with_name(Parameters::changes.as_ref())
+        .required(true)
+        .multiple(true)
+        .value_name("KEY=VALUE|KEY-")
+        .help("The labels to set, as KEY=VALUE, or to remove, as KEY-.");
+
+    let annotation_changes = label_changes
+        .clone()
+        .value_name("KEY=VALUE|KEY=@FILE|KEY-")
+        .help("The annotations to set, as KEY=VALUE or KEY=@FILE to read the value from a file, or to remove, as KEY-.");
+
     let keyout = Arg::with_name(&Parameters::key_output.as_ref())
         .takes_value(true)
         .required(false)
@@ -247,6 +960,37 @@ pub fn parse_arguments() -> ArgMatches<'static> {
         .required(false)
         .help("Output device certificate to file.");
 
+    let create_password = Arg::with_name(Parameters::password.as_ref())
+        .long(Parameters::password.as_ref())
+        .takes_value(true)
+        .value_name("PASSWORD")
+        .conflicts_with_all(&[
+            Other_flags::password_stdin.as_ref(),
+            Parameters::filename.as_ref(),
+        ])
+        .help("Add a password credential to the device. See --password-stdin to keep it out of the shell history.");
+
+    let create_password_stdin = Arg::with_name(Other_flags::password_stdin.as_ref())
+        .long(Other_flags::password_stdin.as_ref())
+        .takes_value(false)
+        .conflicts_with(Parameters::filename.as_ref())
+        .help("Read the password credential of the device from the standard input.");
+
+    let create_psk = Arg::with_name(Parameters::psk.as_ref())
+        .long(Parameters::psk.as_ref())
+        .takes_value(true)
+        .value_name("HEX")
+        .conflicts_with(Parameters::filename.as_ref())
+        .help("Add a pre-shared key credential to the device, given in hexadecimal.");
+
+    let create_output = Arg::with_name(Parameters::output.as_ref())
+        .short("o")
+        .long(Parameters::output.as_ref())
+        .takes_value(true)
+        .value_name("FORMAT")
+        .possible_value(Outputs::id.as_ref())
+        .help("Only print the id of the created resource, e.g. to capture it in a shell variable.");
+
     let device_name_subj = Arg::with_name(&Other_flags::cert.as_ref())
         .long(&Other_flags::cert.as_ref())
         .takes_value(false)
@@ -293,13 +1037,44 @@ pub fn parse_arguments() -> ArgMatches<'static> {
         .about("Allows to manage drogue apps and devices in a drogue-cloud instance")
         .arg(config_file_arg)
         .arg(verbose)
+        .arg(quiet)
+        .arg(strict_json)
         .arg(&context_arg)
+        .arg(remember_app)
+        .arg(check_default_app)
+        .arg(no_config)
+        .arg(strict)
+        .arg(cache)
+        .arg(offline)
+        .arg(dump_http)
+        .arg(audit_file)
+        .arg(curl)
+        .arg(no_env_subst)
+        .arg(no_verify_token)
+        .arg(no_local_config)
+        .arg(trace)
+        .arg(header)
+        .arg(user_agent)
+        .arg(timeout)
+        .arg(connect_timeout)
+        .arg(max_time)
+        .arg(registry_url)
+        .arg(access_token)
+        .arg(token_file)
         .setting(AppSettings::SubcommandRequiredElseHelp)
         .subcommand(
             SubCommand::with_name(Verbs::create.as_ref())
                 .visible_alias("add")
                 .about("create a resource in the drogue-cloud registry")
                 .setting(AppSettings::ArgRequiredElseHelp)
+                .arg(
+                    file_arg
+                        .clone()
+                        .help("Create all the resources of a YAML file, separated by ---. Each one has a kind, app or device.")
+                        .long_help("Create all the resources of a YAML file, separated by ---. Each one has a kind, app or device. \
+                            Devices are created in the app of their metadata.application, or in the default app."),
+                )
+                .arg(&app_id_arg)
                 .subcommand(
                     SubCommand::with_name(Resources::device.as_ref())
                         .about("create a device.")
@@ -307,14 +1082,27 @@ pub fn parse_arguments() -> ArgMatches<'static> {
                         .arg(&app_id_arg)
                         .arg(&spec_arg)
                         .arg(&file_arg)
-                        .arg(&device_name_subj),
+                        .arg(&device_name_subj)
+                        .arg(&create_password)
+                        .arg(&create_password_stdin)
+                        .arg(&create_psk)
+                        .arg(&create_output)
+                        .arg(
+                            Arg::with_name(Parameters::template.as_ref())
+                                .long(Parameters::template.as_ref())
+                                .takes_value(true)
+                                .value_name("NAME")
+                                .conflicts_with(Parameters::filename.as_ref())
+                                .help("Start from a spec template stored with config add-template. --spec is merged into it."),
+                        ),
                 )
                 .subcommand(
                     SubCommand::with_name(Resources::app.as_ref())
                         .about("create an app.")
                         .arg(&resource_id_arg)
                         .arg(&spec_arg)
-                        .arg(&file_arg),
+                        .arg(&file_arg)
+                        .arg(&create_output),
                 ),
         )
         .subcommand(
@@ -327,13 +1115,24 @@ pub fn parse_arguments() -> ArgMatches<'static> {
                         .about("delete a device.")
                         .arg(&resource_id_arg)
                         .arg(&app_id_arg)
-                        .arg(&ignore_missing),
+                        .arg(&ignore_missing)
+                        .arg(&delete_force)
+                        .arg(&yes),
                 )
                 .subcommand(
                     SubCommand::with_name(Resources::app.as_ref())
                         .about("delete an app.")
                         .arg(&resource_id_arg)
-                        .arg(&ignore_missing),
+                        .arg(&ignore_missing)
+                        .arg(&delete_force)
+                        .arg(&yes),
+                )
+                .subcommand(
+                    SubCommand::with_name(Set_targets::member.as_ref())
+                        .about("Remove a member from an app.")
+                        .arg(&member_app)
+                        .arg(&member_user)
+                        .arg(&yes),
                 ),
         )
         .subcommand(
@@ -344,12 +1143,69 @@ pub fn parse_arguments() -> ArgMatches<'static> {
                     SubCommand::with_name(Resources::device.as_ref())
                         .about("Retrieve a device spec.")
                         .arg(resource_id_arg.clone().required(false))
-                        .arg(&app_id_arg),
+                        .arg(&app_id_arg)
+                        .arg(
+                            Arg::with_name(Other_flags::resolve_aliases.as_ref())
+                                .long(Other_flags::resolve_aliases.as_ref())
+                                .takes_value(false)
+                                .help("If there is no device with this id, look for a device with this alias."),
+                        )
+                        .arg(
+                            Arg::with_name(Other_flags::decode_psk.as_ref())
+                                .long(Other_flags::decode_psk.as_ref())
+                                .takes_value(false)
+                                .help("Show the pre-shared keys of the device in hexadecimal. They are redacted otherwise."),
+                        )
+                        .arg(&watch)
+                        .arg(&watch_only_status)
+                        .arg(&watch_interval)
+                        .arg(&history)
+                        .arg(&revision)
+                        .arg(&output_file)
+                        .arg(&output_format)
+                        .arg(&only)
+                        .arg(&columns)
+                        .arg(&label_columns)
+                        .arg(&env_prefix)
+                        .arg(&output_template_file)
+                        .arg(&output_template)
+                        .arg(&save)
+                        .arg(&clipboard)
+                        .arg(&full_timestamps)
+                        .arg(&include_deleted)
+                        .arg(
+                            Arg::with_name(Other_flags::connected.as_ref())
+                                .long(Other_flags::connected.as_ref())
+                                .takes_value(false)
+                                .conflicts_with_all(&[
+                                    Other_flags::watch.as_ref(),
+                                    Other_flags::history.as_ref(),
+                                    Parameters::revision.as_ref(),
+                                ])
+                                .help("Only show whether the device is connected, and since when, on servers reporting it in the device status."),
+                        ),
                 )
                 .subcommand(
                     SubCommand::with_name(Resources::app.as_ref())
                         .about("retrieve an app spec.")
-                        .arg(resource_id_arg.clone().required(false)),
+                        .arg(resource_id_arg.clone().required(false))
+                        .arg(&watch)
+                        .arg(&watch_only_status)
+                        .arg(&watch_interval)
+                        .arg(&history)
+                        .arg(&revision)
+                        .arg(&output_file)
+                        .arg(&output_format)
+                        .arg(&only)
+                        .arg(&columns)
+                        .arg(&label_columns)
+                        .arg(&env_prefix)
+                        .arg(&output_template_file)
+                        .arg(&output_template)
+                        .arg(&save)
+                        .arg(&clipboard)
+                        .arg(&full_timestamps)
+                        .arg(&include_deleted),
                 )
                 // Listing subcommands
                 .subcommand(
@@ -357,14 +1213,58 @@ pub fn parse_arguments() -> ArgMatches<'static> {
                         .about("List all apps.")
                         .arg(&labels)
                         .about("List all apps the user have access to.")
-                        .arg(resource_id_arg.clone().required(false)),
+                        .arg(resource_id_arg.clone().required(false))
+                        .arg(&output_file)
+                        .arg(&output_format)
+                        .arg(&only)
+                        .arg(&columns)
+                        .arg(&label_columns)
+                        .arg(&env_prefix)
+                        .arg(&output_template_file)
+                        .arg(&output_template)
+                        .arg(&full_timestamps)
+                        .arg(&role)
+                        .arg(&sort_by)
+                        .arg(&chunk_size)
+                        .arg(&since)
+                        .arg(&until)
+                        .arg(&include_deleted),
                 )
                 .subcommand(
                     SubCommand::with_name(Resources::devices.as_ref())
                         .arg(&app_id_arg)
                         .arg(&labels)
                         .about("List all devices for an app.")
-                        .arg(resource_id_arg.clone().required(false)),
+                        .arg(
+                            Arg::with_name(Parameters::app_selector.as_ref())
+                                .long(Parameters::app_selector.as_ref())
+                                .takes_value(true)
+                                .use_delimiter(true)
+                                .multiple(true)
+                                .value_name("LABELS")
+                                .help("List the devices of all the apps matching these label filters, instead of a single app. --app is ignored."),
+                        )
+                        .arg(
+                            Arg::with_name(Other_flags::only_connected.as_ref())
+                                .long(Other_flags::only_connected.as_ref())
+                                .takes_value(false)
+                                .help("Only list the connected devices, on servers reporting it in the device status."),
+                        )
+                        .arg(resource_id_arg.clone().required(false))
+                        .arg(&output_file)
+                        .arg(&output_format)
+                        .arg(&only)
+                        .arg(&columns)
+                        .arg(&label_columns)
+                        .arg(&env_prefix)
+                        .arg(&output_template_file)
+                        .arg(&output_template)
+                        .arg(&full_timestamps)
+                        .arg(&sort_by)
+                        .arg(&chunk_size)
+                        .arg(&since)
+                        .arg(&until)
+                        .arg(&include_deleted),
                 ),
         )
         .subcommand(
@@ -377,13 +1277,19 @@ pub fn parse_arguments() -> ArgMatches<'static> {
                         .about("Edit a device spec.")
                         .arg(&resource_id_arg)
                         .arg(&app_id_arg)
-                        .arg(&file_arg),
+                        .arg(&file_arg)
+                        .arg(&resource_version)
+                        .arg(&edit_path)
+                        .arg(&yes),
                 )
                 .subcommand(
                     SubCommand::with_name(Resources::app.as_ref())
                         .about("Edit an app spec.")
                         .arg(&resource_id_arg)
-                        .arg(&file_arg),
+                        .arg(&file_arg)
+                        .arg(&resource_version)
+                        .arg(&edit_path)
+                        .arg(&yes),
                 ),
         )
         .subcommand(
@@ -399,15 +1305,108 @@ pub fn parse_arguments() -> ArgMatches<'static> {
                 .subcommand(
                     SubCommand::with_name(Set_targets::password.as_ref())
                         .about("Set a password credentials for a device")
-                        .arg(&set_arg)
+                        .arg(&set_password_arg)
                         .arg(&app_id_arg)
-                        .arg(&set_password_username),
+                        .arg(&set_password_username)
+                        .arg(&set_password_generate)
+                        .arg(&set_password_length),
                 )
                 .subcommand(
                     SubCommand::with_name(Set_targets::alias.as_ref())
                         .about("Add an alias for a device")
                         .arg(&set_arg)
                         .arg(&app_id_arg),
+                )
+                .subcommand(
+                    SubCommand::with_name(Set_targets::member.as_ref())
+                        .about("Add a member to an app, or change its role.")
+                        .arg(&member_app)
+                        .arg(&member_user)
+                        .arg(&member_role),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name(Verbs::transfer.as_ref())
+                .about("Transfer the ownership of a resource to another user")
+                .setting(AppSettings::ArgRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name(Resources::app.as_ref())
+                        .about("Start the transfer of an app, the new owner has to accept it.")
+                        .arg(&resource_id_arg)
+                        .arg(
+                            Arg::with_name(Set_args::to.as_ref())
+                                .long(Set_args::to.as_ref())
+                                .required(true)
+                                .takes_value(true)
+                                .value_name("USER_ID")
+                                .help("The id of the new owner."),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name(Verbs::accept.as_ref())
+                .about("Accept the ownership of a resource transferred to you")
+                .setting(AppSettings::ArgRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name(Resources::app.as_ref())
+                        .about("Accept the ownership of an app.")
+                        .arg(&resource_id_arg),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name(Verbs::label.as_ref())
+                .about("Add, update or remove the labels of resources")
+                .setting(AppSettings::ArgRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name(Resources::device.as_ref())
+                        .about("Change the labels of a device.")
+                        .arg(&resource_id_arg)
+                        .arg(&label_changes)
+                        .arg(&app_id_arg),
+                )
+                .subcommand(
+                    SubCommand::with_name(Resources::devices.as_ref())
+                        .about("Change the labels of the devices matching a label selector.")
+                        .arg(&label_changes)
+                        .arg(&app_id_arg)
+                        .arg(labels.clone().required(true).help(
+                            "A comma separated list of the label filters selecting the devices.",
+                        )),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name(Verbs::annotate.as_ref())
+                .about("Add, update or remove the annotations of resources")
+                .setting(AppSettings::ArgRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name(Resources::device.as_ref())
+                        .about("Change the annotations of a device.")
+                        .arg(&resource_id_arg)
+                        .arg(&annotation_changes)
+                        .arg(&app_id_arg),
+                )
+                .subcommand(
+                    SubCommand::with_name(Resources::devices.as_ref())
+                        .about("Change the annotations of the devices matching a label selector.")
+                        .arg(&annotation_changes)
+                        .arg(&app_id_arg)
+                        .arg(labels.clone().required(true).help(
+                            "A comma separated list of the label filters selecting the devices.",
+                        )),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name(Verbs::rollback.as_ref())
+                .about("Restore a previous spec of a resource")
+                .setting(AppSettings::ArgRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name(Resources::device.as_ref())
+                        .about("Restore a previous spec of a device.")
+                        .arg(&resource_id_arg)
+                        .arg(&app_id_arg)
+                        .arg(&to_revision)
+                        .arg(&from_file)
+                        .arg(&yes),
                 ),
         )
         .subcommand(
@@ -415,8 +1414,24 @@ pub fn parse_arguments() -> ArgMatches<'static> {
                 .about("Send a command to a device")
                 .setting(AppSettings::ArgRequiredElseHelp)
                 .arg(&cmd_arg)
+                .arg(&cmd_device_arg)
                 .arg(&app_id_arg)
                 .arg(&payload_arg)
+                .arg(&count_arg)
+                .arg(&interval_arg)
+                .arg(&all_devices)
+                .arg(&yes)
+                .arg(&concurrency)
+                .arg(
+                    Arg::with_name(Parameters::output.as_ref())
+                        .short("o")
+                        .long(Parameters::output.as_ref())
+                        .takes_value(true)
+                        .value_name("FORMAT")
+                        .possible_value(Outputs::json.as_ref())
+                        .conflicts_with(Parameters::count.as_ref())
+                        .help("Print the result as JSON. With --all-devices, the result for each device is included."),
+                )
                 .arg(
                     file_arg
                         .clone()
@@ -433,7 +1448,11 @@ pub fn parse_arguments() -> ArgMatches<'static> {
                 .arg(&token_arg)
                 .about("Log into a drogue cloud installation.")
                 .arg(&url_arg)
-                .arg(&login_keep_current),
+                .arg(&login_keep_current)
+                .arg(&login_timeout)
+                .arg(&login_scope)
+                .arg(&login_audience)
+                .arg(&login_client_id),
         )
         .subcommand(
             SubCommand::with_name(Other_commands::whoami.as_ref())
@@ -445,6 +1464,12 @@ pub fn parse_arguments() -> ArgMatches<'static> {
                         .help("print a valid bearer token for the drogue cloud instance.")
                         .conflicts_with(Other_commands::endpoints.as_ref()),
                 )
+                .arg(
+                    clipboard
+                        .clone()
+                        .requires(Other_commands::token.as_ref())
+                        .help("Copy the token to the clipboard instead of printing it."),
+                )
                 .subcommand(
                     SubCommand::with_name(Other_commands::endpoints.as_ref())
                         .about("List drogue-cloud available endpoints.")
@@ -483,17 +1508,29 @@ pub fn parse_arguments() -> ArgMatches<'static> {
                 )
                 .subcommand(
                     SubCommand::with_name(Context_subcommands::delete.as_ref())
-                        .alias("remove")
+                        .aliases(&["remove", "delete-context"])
                         .about("Delete a context")
-                        .arg(&context_id_arg),
+                        .arg(&context_id_arg)
+                        .arg(
+                            Arg::with_name(Other_flags::force.as_ref())
+                                .long(Other_flags::force.as_ref())
+                                .takes_value(false)
+                                .help("Allow deleting the last context of the configuration file."),
+                        ),
                 )
                 .subcommand(
                     SubCommand::with_name("set-default-app")
                         .about("Set a default-app for a context.")
                         .arg(&resource_id_arg),
                 )
+                .subcommand(
+                    SubCommand::with_name(Context_subcommands::current_context.as_ref())
+                        .alias("current")
+                        .about("Print the name of the active context"),
+                )
                 .subcommand(
                     SubCommand::with_name(Context_subcommands::rename.as_ref())
+                        .alias("rename-context")
                         .about("Rename a context.")
                         .arg(&context_id_arg)
                         .arg(
@@ -506,6 +1543,58 @@ pub fn parse_arguments() -> ArgMatches<'static> {
                     SubCommand::with_name(Context_subcommands::set_default_algo.as_ref())
                         .about("Set a default key generation algorithm for a context.")
                         .arg(&algo_param),
+                )
+                .subcommand(
+                    SubCommand::with_name(Context_subcommands::set.as_ref())
+                        .about("Set a key of the context, e.g. default_app or registry_url.")
+                        .arg(
+                            Arg::with_name("key")
+                                .required(true)
+                                .help("The key, as named in the configuration file."),
+                        )
+                        .arg(Arg::with_name("value").required(true).help("The new value."))
+                        .arg(
+                            Arg::with_name(Other_flags::force.as_ref())
+                                .long(Other_flags::force.as_ref())
+                                .takes_value(false)
+                                .help("Allow setting the keys filled at login, and unknown keys."),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name(Context_subcommands::unset.as_ref())
+                        .about("Clear a key of the context, e.g. default_app.")
+                        .arg(
+                            Arg::with_name("key")
+                                .required(true)
+                                .help("The key, as named in the configuration file."),
+                        )
+                        .arg(
+                            Arg::with_name(Other_flags::force.as_ref())
+                                .long(Other_flags::force.as_ref())
+                                .takes_value(false)
+                                .help("Allow clearing the token, which logs out of the context."),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name(Context_subcommands::add_template.as_ref())
+                        .about("Store a device spec as a named template, to create devices with create device --template. Environment variables are substituted when it is used.")
+                        .arg(
+                            Arg::with_name(Parameters::template.as_ref())
+                                .required(true)
+                                .value_name("NAME")
+                                .help("The name of the template. An existing template with this name is replaced."),
+                        )
+                        .arg(
+                            file_arg
+                                .clone()
+                                .required(true)
+                                .help("File containing the device spec, as JSON or YAML."),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name(Context_subcommands::migrate.as_ref()).about(
+                        "Upgrade the configuration file to the current format. This is also done when loading it.",
+                    ),
                 ),
         )
         .subcommand(
@@ -543,21 +1632,244 @@ pub fn parse_arguments() -> ArgMatches<'static> {
                         .help("The id of the application to subscribe to."),
                 ),
         )
-        .get_matches()
+        .subcommand(
+            SubCommand::with_name(Other_commands::status.as_ref())
+                .about("Check the configuration, the connection to drogue cloud and the token."),
+        )
+        .subcommand(
+            SubCommand::with_name(Other_commands::doctor.as_ref())
+                .about("Diagnose common setup problems and explain how to fix them."),
+        )
+        .subcommand(
+            SubCommand::with_name(Other_commands::ping.as_ref())
+                .about("Measure the latency of requests to the registry, and check the token.")
+                .arg(
+                    Arg::with_name(Parameters::count.as_ref())
+                        .long(Parameters::count.as_ref())
+                        .short("n")
+                        .takes_value(true)
+                        .value_name("N")
+                        .help("The number of requests. [default: 5]")
+                        .validator(|n| match n.parse::<usize>() {
+                            Ok(n) if n > 0 => Ok(()),
+                            _ => Err(String::from("The value must be a positive integer")),
+                        }),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name(Other_commands::schema.as_ref())
+                .about("Print the JSON schema of the spec of a resource, to validate specs.")
+                .arg(
+                    Arg::with_name(Other_commands::schema.as_ref())
+                        .required(true)
+                        .value_name("RESOURCE")
+                        .possible_values(&[Resources::app.as_ref(), Resources::device.as_ref()]),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name(Other_commands::explain.as_ref())
+                .about("Describe a field of the spec of a resource, and the fields it contains.")
+                .arg(
+                    Arg::with_name(Other_commands::explain.as_ref())
+                        .required(true)
+                        .value_name("FIELD")
+                        .help("The resource and the path of the field, e.g. device.spec.gatewaySelector"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name(Other_commands::tree.as_ref())
+                .about("Print the apps with their devices nested beneath them.")
+                .arg(
+                    Arg::with_name(Parameters::depth.as_ref())
+                        .long(Parameters::depth.as_ref())
+                        .takes_value(true)
+                        .value_name("N")
+                        .possible_values(&["1", "2"])
+                        .help("1 only prints the apps, 2 prints their devices too. [default: 2]"),
+                )
+                .arg(
+                    Arg::with_name(Parameters::width.as_ref())
+                        .long(Parameters::width.as_ref())
+                        .takes_value(true)
+                        .value_name("N")
+                        .help("Print at most N apps, and N devices per app.")
+                        .validator(|n| match n.parse::<usize>() {
+                            Ok(n) if n > 0 => Ok(()),
+                            _ => Err(String::from("The value must be a positive integer")),
+                        }),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name(Other_commands::export.as_ref())
+                .about("Write resources to files, one file per resource.")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name(Resources::device.as_ref())
+                        .visible_alias(Resources::devices.as_ref())
+                        .about("Write a device, or all the devices of an app, to files.")
+                        .arg(
+                            resource_id_arg
+                                .clone()
+                                .required_unless(Other_flags::all.as_ref())
+                                .conflicts_with(Other_flags::all.as_ref()),
+                        )
+                        .arg(&app_id_arg)
+                        .arg(
+                            Arg::with_name(Other_flags::all.as_ref())
+                                .long(Other_flags::all.as_ref())
+                                .takes_value(false)
+                                .help("Export all the devices of the app."),
+                        )
+                        .arg(
+                            Arg::with_name(Parameters::out_dir.as_ref())
+                                .long(Parameters::out_dir.as_ref())
+                                .takes_value(true)
+                                .value_name("DIR")
+                                .required(true)
+                                .help("The directory to write the files to. It is created if missing."),
+                        )
+                        .arg(
+                            Arg::with_name(Parameters::name_template.as_ref())
+                                .long(Parameters::name_template.as_ref())
+                                .takes_value(true)
+                                .value_name("TEMPLATE")
+                                .help("The file name, where {field} is replaced by a metadata field of the device, e.g. {name} or {labels.env}. [default: {name}.json]"),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name(Other_commands::completion.as_ref())
+                .about("Print the shell completion script, or install it for the current user.")
+                .arg(
+                    Arg::with_name(Other_commands::completion.as_ref())
+                        .value_name("SHELL")
+                        .possible_values(&Shell::variants())
+                        .help("The shell to complete. Detected from $SHELL if omitted."),
+                )
+                .arg(
+                    Arg::with_name(Other_flags::install.as_ref())
+                        .long(Other_flags::install.as_ref())
+                        .takes_value(false)
+                        .help("Write the completion where the shell loads it from, instead of printing it."),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name(Other_commands::manpage.as_ref())
+                .about("Print the man page of drg.")
+                .setting(AppSettings::Hidden),
+        )
+        .subcommand(
+            SubCommand::with_name(Other_commands::complete_ids.as_ref())
+                .about("Print the ids of the apps or devices, for the completion scripts.")
+                .setting(AppSettings::Hidden)
+                .arg(
+                    Arg::with_name(Other_commands::complete_ids.as_ref())
+                        .required(true)
+                        .possible_values(&[Resources::apps.as_ref(), Resources::devices.as_ref()]),
+                )
+                .arg(&app_id_arg),
+        )
 }
 
+// The app is resolved in this order : --app, the last used app if --remember-app is set,
+// the default app of the project in the current directory, the default app of the context.
 pub fn get_app_id<'a>(matches: &'a ArgMatches, config: &'a Context) -> Result<AppId> {
-    match matches.value_of(Resources::app) {
-        Some(a) => Ok(a.to_string()),
-        None => config
-            .default_app
-            .as_ref()
-            .map(|v| {
-                println!("Using default app \"{}\".", &v);
-                v.to_string()
-            })
-            .ok_or_else(|| {
-                anyhow!("Missing app argument and no default app specified in config file.")
-            }),
+    if let Some(a) = matches.value_of(Resources::app) {
+        return Ok(a.to_string());
+    }
+    if let Some(last) = config
+        .last_app
+        .as_ref()
+        .filter(|_| matches.is_present(Other_flags::remember_app))
+    {
+        util::app_notice(&format!("Using last app \"{}\".", last));
+        return Ok(last.to_string());
+    }
+    if let Some((app, path)) = config::project_default_app() {
+        util::app_notice(&format!(
+            "Using project default app \"{}\" from {}.",
+            app,
+            path.display()
+        ));
+        return Ok(app);
+    }
+
+    let default = config.default_app.as_ref().ok_or_else(|| {
+        anyhow!("Missing app argument and no default app specified in config file.")
+    })?;
+    util::app_notice(&format!("Using default app \"{}\".", default));
+
+    // the default app may have been deleted since it was set.
+    if matches.is_present(Other_flags::check_default_app) && !apps::exists(config, default)? {
+        return Err(anyhow!(
+            "The default app \"{}\" does not exist anymore. Use --app, or update the default with `drg context set-default-app <appId>`.",
+            default
+        ));
+    }
+    Ok(default.to_string())
+}
+
+pub fn get_output<'a>(matches: &'a ArgMatches) -> Result<Output<'a>> {
+    Ok(Output {
+        format: matches
+            .value_of(Parameters::output)
+            .map(Outputs::from_str)
+            .transpose()?,
+        file: matches.value_of(Parameters::output_file),
+        sort_by: matches.value_of(Parameters::sort_by),
+        columns: matches
+            .values_of(Parameters::columns)
+            .map(|c| c.collect())
+            .unwrap_or_default(),
+        only: matches.value_of(Parameters::only),
+        full_timestamps: matches.is_present(Other_flags::full_timestamps),
+        chunk_size: matches
+            .value_of(Parameters::chunk_size)
+            .map(|n| n.parse())
+            .transpose()?,
+        since: matches
+            .value_of(Parameters::since)
+            .map(util::parse_time)
+            .transpose()?,
+        until: matches
+            .value_of(Parameters::until)
+            .map(util::parse_time)
+            .transpose()?,
+        label_columns: matches
+            .values_of(Parameters::label_columns)
+            .map(|l| l.collect())
+            .unwrap_or_default(),
+        env_prefix: matches.value_of(Parameters::env_prefix),
+        save: matches.value_of(Parameters::save),
+        clipboard: matches.is_present(Other_flags::clipboard),
+        template: match matches.value_of(Parameters::output_template) {
+            Some(template) => Some(util::check_template(template)?.to_string()),
+            None => matches
+                .value_of(Parameters::output_template_file)
+                .map(util::read_template)
+                .transpose()?,
+        },
+    })
+}
+
+#[cfg(test)]
+mod arguments_test {
+    use super::*;
+
+    #[test]
+    fn test_remove_member() {
+        for verb in &["remove", "delete"] {
+            let matches = app()
+                .get_matches_from_safe(vec!["drg", verb, "member", "my-app", "--user", "foo"])
+                .unwrap();
+            let (command, submatches) = matches.subcommand();
+            assert_eq!(command, Verbs::delete.as_ref());
+            let member = submatches
+                .unwrap()
+                .subcommand_matches(Set_targets::member)
+                .unwrap();
+            assert_eq!(member.value_of(Parameters::id), Some("my-app"));
+            assert_eq!(member.value_of(Set_args::user), Some("foo"));
+        }
     }
 }
@@ -0,0 +1,56 @@
+use crate::config::Context;
+use crate::openid;
+
+use anyhow::Result;
+use chrono::Utc;
+use once_cell::sync::Lazy;
+use reqwest::{Method, Url};
+use serde_json::json;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::sync::Mutex;
+
+static AUDIT_LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+// Append a JSON line for a request changing a resource to the --audit-file.
+// Failing to do so warns, but does not fail the command.
+pub fn record(
+    path: &str,
+    context: &Context,
+    method: &Method,
+    url: &Url,
+    res: &Result<reqwest::blocking::Response>,
+) {
+    let outcome = match res {
+        Ok(res) => res.status().to_string(),
+        Err(e) => format!("error: {}", e),
+    };
+    let entry = json!({
+        "timestamp": Utc::now().to_rfc3339(),
+        "user": openid::user_id(context).unwrap_or_else(|_| "unknown".to_string()),
+        "context": context.name,
+        "method": method.as_str(),
+        "resource": resource(url),
+        "outcome": outcome,
+    });
+
+    let _lock = AUDIT_LOCK.lock().unwrap();
+    let written = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut f| writeln!(f, "{}", entry));
+
+    if let Err(e) = written {
+        log::warn!("Could not write to the audit file {} : {}", path, e);
+    }
+}
+
+// The path of the resource, without the api prefix, e.g. apps/myApp/devices/myDevice.
+fn resource(url: &Url) -> String {
+    let path = url.path().trim_start_matches('/');
+    match path.strip_prefix("api/") {
+        Some(rest) => rest.splitn(3, '/').nth(2).unwrap_or(rest).to_string(),
+        None => path.to_string(),
+    }
+}
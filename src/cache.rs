@@ -0,0 +1,138 @@
+use crate::util;
+
+use anyhow::{anyhow, Context as AnyhowContext, Result};
+use chrono::{DateTime, Duration, Utc};
+use dirs::cache_dir;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// A response body saved with --cache, to be read back with --offline.
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    url: String,
+    timestamp: DateTime<Utc>,
+    body: String,
+}
+
+// How long listed names are reused, e.g. by completions called on each tab press.
+const NAMES_TTL_SECONDS: i64 = 5;
+
+// The names of the resources of a list, kept for a few seconds.
+#[derive(Serialize, Deserialize)]
+struct Names {
+    key: String,
+    timestamp: DateTime<Utc>,
+    names: Vec<String>,
+}
+
+// FNV-1a, the file names must stay the same across builds and rust versions.
+fn stable_hash(key: &str) -> u64 {
+    key.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+fn cache_path(dir: &str, key: &str) -> Result<PathBuf> {
+    let cache = cache_dir().ok_or_else(|| anyhow!("Cannot find a cache directory"))?;
+    Ok(cache
+        .join(dir)
+        .join(format!("{:016x}.json", stable_hash(key))))
+}
+
+// Cached responses may hold private data, so only the user can read them.
+#[cfg(unix)]
+fn create_cache_dir(dir: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::DirBuilderExt;
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)
+}
+
+#[cfg(not(unix))]
+fn create_cache_dir(dir: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(dir)
+}
+
+// Entries are keyed by the full url, query included.
+fn entry_path(url: &str) -> Result<PathBuf> {
+    cache_path("drg", url)
+}
+
+// The names listed for `key` less than a few seconds ago.
+pub fn load_names(key: &str) -> Option<Vec<String>> {
+    let content = fs::read_to_string(cache_path("drg/names", key).ok()?).ok()?;
+    let names: Names = serde_json::from_str(&content).ok()?;
+    (names.key == key && Utc::now() - names.timestamp < Duration::seconds(NAMES_TTL_SECONDS))
+        .then(|| names.names)
+}
+
+pub fn store_names(key: &str, names: &[String]) -> Result<()> {
+    let path = cache_path("drg/names", key)?;
+    if let Some(parent) = path.parent() {
+        create_cache_dir(parent)?;
+    }
+
+    let names = Names {
+        key: key.to_string(),
+        timestamp: Utc::now(),
+        names: names.to_vec(),
+    };
+    util::write_file_atomic(
+        path.to_str().unwrap_or_default(),
+        &serde_json::to_string(&names)?,
+    )
+}
+
+// Called when a resource is changed, the names may not be the same anymore.
+pub fn invalidate_names() {
+    if let Ok(path) = cache_path("drg/names", "") {
+        if let Some(dir) = path.parent() {
+            if let Err(e) = fs::remove_dir_all(dir) {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    log::debug!("Could not clear the cached names : {}", e);
+                }
+            }
+        }
+    }
+}
+
+pub fn store(url: &str, body: &[u8]) -> Result<()> {
+    let path = entry_path(url)?;
+    if let Some(parent) = path.parent() {
+        create_cache_dir(parent)?;
+    }
+
+    let entry = Entry {
+        url: url.to_string(),
+        timestamp: Utc::now(),
+        body: String::from_utf8_lossy(body).to_string(),
+    };
+    util::write_file_atomic(
+        path.to_str().unwrap_or_default(),
+        &serde_json::to_string(&entry)?,
+    )
+}
+
+// The cached body, with a warning on stderr as it may be outdated.
+pub fn load(url: &str) -> Result<Vec<u8>> {
+    let no_entry = || {
+        anyhow!(
+            "No cached response for {}, run the command with --cache while online first.",
+            url
+        )
+    };
+
+    let content = fs::read_to_string(entry_path(url)?).map_err(|_| no_entry())?;
+    let entry: Entry = serde_json::from_str(&content).context("Invalid cache entry")?;
+    if entry.url != url {
+        return Err(no_entry());
+    }
+
+    eprintln!(
+        "Offline : this response was cached {} ago and may be stale.",
+        util::age(&entry.timestamp.to_rfc3339())?
+    );
+    Ok(entry.body.into_bytes())
+}
@@ -1,20 +1,211 @@
 use crate::config::Context;
-use crate::util;
+use crate::{http, util, DeviceId};
 
-use anyhow::{Context as anyhowContext, Result};
-use oauth2::TokenResponse;
-use reqwest::blocking::Client;
+use anyhow::{anyhow, Context as anyhowContext, Result};
 use reqwest::StatusCode;
-use serde_json::Value;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
 
+// With `json`, the result is printed as an object with the status and the message of the server.
 pub fn send_command(
     config: &Context,
     app: &str,
     device: &str,
     command: &str,
     body: Value,
+    json: bool,
 ) -> Result<()> {
-    let client = Client::new();
+    let res = post(config, app, device, command, &body)?;
+    if res.status() != StatusCode::ACCEPTED {
+        util::exit_with_code(res.status())
+    }
+
+    let message = res.text().unwrap_or_default();
+    let message = message.trim();
+    if json {
+        let mut result = json!({
+            "sent": true,
+            "device": device,
+            "command": command,
+            "status": res.status().as_u16(),
+        });
+        if !message.is_empty() {
+            result["message"] = json!(message);
+        }
+        println!("{}", serde_json::to_string_pretty(&result)?);
+    } else if message.is_empty() {
+        println!("Command {} accepted", command);
+    } else {
+        println!("Command {} accepted : {}", command, message);
+    }
+    Ok(())
+}
+
+// Send the same command `count` times, waiting `interval` between each of them.
+pub fn send_command_repeat(
+    config: &Context,
+    app: &str,
+    device: &str,
+    command: &str,
+    body: Value,
+    count: u32,
+    interval: Duration,
+) -> Result<()> {
+    let mut accepted = 0;
+
+    for i in 1..=count {
+        if i > 1 {
+            thread::sleep(interval);
+        }
+        if http::deadline_exceeded() {
+            eprintln!("The --max-time is exceeded, stopping.");
+            break;
+        }
+
+        match post_command(config, app, device, command, &body) {
+            Ok(StatusCode::ACCEPTED) => {
+                log::info!("Command {} accepted ({}/{})", command, i, count);
+                accepted += 1;
+            }
+            Ok(r) => log::error!("Command {} rejected ({}/{}) : {}", command, i, count, r),
+            Err(e) => log::error!("Command {} failed ({}/{}) : {}", command, i, count, e),
+        }
+    }
+
+    println!("{}/{} commands {} accepted", accepted, count, command);
+    if accepted == count {
+        Ok(())
+    } else {
+        Err(anyhow!("{} command(s) failed", count - accepted))
+    }
+}
+
+// What happened to the command sent to one of the devices.
+#[derive(Serialize)]
+pub struct Outcome {
+    resource: DeviceId,
+    result: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<String>,
+}
+
+impl Outcome {
+    pub fn succeeded(&self) -> bool {
+        self.result == "accepted"
+    }
+}
+
+// Send the command to all the given devices, using `concurrency` parallel workers.
+// The outcome for each device is returned, the summary is printed with `print_summary`.
+pub fn broadcast_command(
+    config: &Context,
+    app: &str,
+    devices: Vec<DeviceId>,
+    command: &str,
+    body: Value,
+    concurrency: usize,
+) -> Vec<Outcome> {
+    let total = devices.len();
+    let queue = Arc::new(Mutex::new(devices));
+    let (tx, rx) = mpsc::channel();
+
+    let workers: Vec<_> = (0..concurrency.min(total))
+        .map(|_| {
+            let queue = Arc::clone(&queue);
+            let tx = tx.clone();
+            let config = config.clone();
+            let app = app.to_string();
+            let command = command.to_string();
+            let body = body.clone();
+
+            thread::spawn(move || loop {
+                let device = match queue.lock().unwrap().pop() {
+                    Some(device) => device,
+                    None => break,
+                };
+                let result = post_command(&config, &app, &device, &command, &body);
+                if tx.send((device, result)).is_err() {
+                    break;
+                }
+            })
+        })
+        .collect();
+    // only the workers should keep the channel open.
+    drop(tx);
+
+    let mut outcomes = Vec::with_capacity(total);
+    for (device, result) in rx {
+        let (result, detail) = match result {
+            Ok(StatusCode::ACCEPTED) => {
+                log::info!("Command {} accepted by {}", command, device);
+                ("accepted", None)
+            }
+            Ok(r) => {
+                log::error!("Command {} rejected for {} : {}", command, device, r);
+                ("rejected", Some(r.to_string()))
+            }
+            Err(e) => {
+                log::error!("Command {} failed for {} : {}", command, device, e);
+                ("failed", Some(e.to_string()))
+            }
+        };
+        outcomes.push(Outcome {
+            resource: device,
+            result,
+            detail,
+        });
+    }
+
+    for worker in workers {
+        let _ = worker.join();
+    }
+
+    outcomes
+}
+
+// A one line summary, or with `json` the outcome for each device.
+pub fn print_summary(outcomes: &[Outcome], command: &str, json: bool) -> Result<()> {
+    let accepted = outcomes.iter().filter(|o| o.succeeded()).count();
+    if json {
+        let summary = json!({
+            "command": command,
+            "accepted": accepted,
+            "total": outcomes.len(),
+            "results": outcomes,
+        });
+        println!("{}", serde_json::to_string_pretty(&summary)?);
+    } else {
+        println!(
+            "{}/{} devices accepted command {}",
+            accepted,
+            outcomes.len(),
+            command
+        );
+    }
+    Ok(())
+}
+
+fn post_command(
+    config: &Context,
+    app: &str,
+    device: &str,
+    command: &str,
+    body: &Value,
+) -> Result<StatusCode> {
+    post(config, app, device, command, body).map(|res| res.status())
+}
+
+fn post(
+    config: &Context,
+    app: &str,
+    device: &str,
+    command: &str,
+    body: &Value,
+) -> Result<http::Response> {
+    let client = http::client();
     let url = format!(
         "{}{}/apps/{}/devices/{}",
         &config.registry_url,
@@ -23,16 +214,11 @@ pub fn send_command(
         device
     );
 
-    client
+    let req = client
         .post(&url)
         .header(reqwest::header::CONTENT_TYPE, "application/json")
-        .bearer_auth(&config.token.access_token().secret())
         .query(&[("command", command)])
-        .body(body.to_string())
-        .send()
-        .context("Can't send command.")
-        .map(|res| match res.status() {
-            StatusCode::ACCEPTED => println!("Command {} accepted", command),
-            r => util::exit_with_code(r),
-        })
+        .body(body.to_string());
+
+    http::send(config, req).context("Can't send command.")
 }
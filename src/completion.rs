@@ -0,0 +1,163 @@
+use crate::arguments::{self, Resources};
+use crate::config::{self, Config, ContextId};
+use crate::{apps, devices};
+use anyhow::{anyhow, Context as AnyhowContext, Result};
+use clap::Shell;
+use dirs::{config_dir, data_dir, home_dir};
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+// Wraps the generated _drg function to complete the app ids after --app or app,
+// and the device ids after device, with the names listed by `drg complete-ids`.
+const BASH_IDS: &str = r#"
+_drg_ids() {
+    local cur="${COMP_WORDS[COMP_CWORD]}" prev="${COMP_WORDS[COMP_CWORD-1]}"
+    local app="" i
+    for ((i = 1; i < COMP_CWORD; i++)); do
+        case "${COMP_WORDS[i]}" in
+            -a|--app|--application) app="${COMP_WORDS[i+1]}" ;;
+        esac
+    done
+    if [[ "$cur" != -* ]]; then
+        case "$prev" in
+            -a|--app|--application|app)
+                COMPREPLY=($(compgen -W "$(drg complete-ids apps 2>/dev/null)" -- "$cur"))
+                return 0 ;;
+            device)
+                COMPREPLY=($(compgen -W "$(drg complete-ids devices ${app:+--app "$app"} 2>/dev/null)" -- "$cur"))
+                return 0 ;;
+        esac
+    fi
+    _drg "$@"
+}
+complete -F _drg_ids -o bashdefault -o default drg
+"#;
+
+// The devices are the ones of the app given with --app on the command line, as with bash.
+const FISH_IDS: &str = r#"
+function __drg_device_ids
+    set -l tokens (commandline -opc)
+    set -l app
+    for i in (seq 2 (count $tokens))
+        switch $tokens[(math $i - 1)]
+            case -a --app --application
+                set app --app $tokens[$i]
+        end
+    end
+    drg complete-ids devices $app 2>/dev/null
+end
+complete -c drg -s a -l app -l application -x -a '(drg complete-ids apps 2>/dev/null)'
+complete -c drg -n '__fish_seen_subcommand_from app' -f -a '(drg complete-ids apps 2>/dev/null)'
+complete -c drg -n '__fish_seen_subcommand_from device' -f -a '(__drg_device_ids)'
+"#;
+
+// The shell given as argument, or the one from $SHELL.
+pub fn shell(name: Option<&str>) -> Result<Shell> {
+    let name = match name {
+        Some(name) => name.to_string(),
+        None => env::var("SHELL")
+            .ok()
+            .and_then(|s| s.rsplit('/').next().map(|s| s.to_string()))
+            .ok_or_else(|| anyhow!("Cannot detect the shell from $SHELL, please specify it."))?,
+    };
+
+    Shell::from_str(&name).map_err(|_| {
+        anyhow!(
+            "Unsupported shell {}, expected one of {}",
+            name,
+            Shell::variants().join(", ")
+        )
+    })
+}
+
+pub fn print(shell: Shell) {
+    arguments::app().gen_completions_to("drg", shell, &mut io::stdout());
+    print!("{}", ids_completion(shell));
+}
+
+// Bash and fish also complete the ids of the apps and devices.
+fn ids_completion(shell: Shell) -> &'static str {
+    match shell {
+        Shell::Bash => BASH_IDS,
+        Shell::Fish => FISH_IDS,
+        _ => "",
+    }
+}
+
+// The names of the apps, or of the devices of the app, one per line, for the completion scripts.
+// Nothing is printed when they can't be listed, e.g. when offline.
+pub fn print_ids(
+    config: &Result<Config>,
+    context_name: &Option<ContextId>,
+    resource: Resources,
+    app: Option<&str>,
+) {
+    let context = match config.as_ref().map(|c| c.get_context(context_name)) {
+        Ok(Ok(context)) => context,
+        _ => return,
+    };
+    let names = match resource {
+        Resources::app | Resources::apps => apps::names(context),
+        Resources::device | Resources::devices => {
+            // like get_app_id, the project default app comes before the one of the context.
+            match app
+                .map(|a| a.to_string())
+                .or_else(|| config::project_default_app().map(|(app, _)| app))
+                .or_else(|| context.default_app.clone())
+            {
+                Some(app) => devices::names(context, &app, None),
+                None => return,
+            }
+        }
+    };
+    if let Ok(names) = names {
+        for name in names {
+            println!("{}", name);
+        }
+    }
+}
+
+// Write the completion script where the shell loads it from for the current user.
+pub fn install(shell: Shell) -> Result<()> {
+    let path = install_path(shell)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut file = File::create(&path)
+        .context(format!("Cannot write the completion to {}", path.display()))?;
+    arguments::app().gen_completions_to("drg", shell, &mut file);
+    file.write_all(ids_completion(shell).as_bytes())?;
+
+    println!("Installed the {} completion in {}", shell, path.display());
+    if let Shell::Zsh = shell {
+        println!(
+            "Make sure {} is in your fpath, e.g. add `fpath+={}` to ~/.zshrc before compinit.",
+            path.parent().unwrap().display(),
+            path.parent().unwrap().display()
+        );
+    }
+    println!("Start a new shell to use it.");
+    Ok(())
+}
+
+fn install_path(shell: Shell) -> Result<PathBuf> {
+    let missing = || anyhow!("Cannot find the home directory to install the completion");
+    match shell {
+        Shell::Bash => Ok(data_dir()
+            .ok_or_else(missing)?
+            .join("bash-completion/completions/drg")),
+        Shell::Zsh => Ok(home_dir().ok_or_else(missing)?.join(".zfunc/_drg")),
+        Shell::Fish => Ok(config_dir()
+            .ok_or_else(missing)?
+            .join("fish/completions/drg.fish")),
+        _ => Err(anyhow!(
+            "Installing the {} completion is not supported, redirect `drg completion {}` instead.",
+            shell,
+            shell
+        )),
+    }
+}
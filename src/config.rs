@@ -2,22 +2,40 @@ use crate::trust::SignAlgo;
 
 use anyhow::{anyhow, Context as AnyhowContext, Result};
 use serde::{Deserialize, Serialize};
-use std::{env, fs::create_dir_all, fs::write, fs::File, path::Path, process::exit};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::{collections::BTreeMap, env, fs::File, path::Path, process::exit, str::FromStr};
 
 use crate::AppId;
 use chrono::{DateTime, Utc};
 use core::fmt;
 use dirs::config_dir;
-use oauth2::basic::BasicTokenResponse;
+use oauth2::basic::{BasicTokenResponse, BasicTokenType};
+use oauth2::{AccessToken, EmptyExtraTokenFields};
+use serde_json::{json, Value};
 use tabular::{Row, Table};
 use url::Url;
 
 pub type ContextId = String;
 
+// Bumped when the schema of the config file changes, see `migrate`.
+pub const CONFIG_VERSION: u64 = 1;
+
+// Per project config files, looked for in the current directory and its ancestors.
+const LOCAL_CONFIG_FILES: &[&str] = &[".drgconfig.yaml", ".drgconfig.json"];
+
+// Whether the local config files are used, see --no-local-config.
+static LOCAL_CONFIG: AtomicBool = AtomicBool::new(true);
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Config {
+    #[serde(default)]
+    pub version: u64,
     pub active_context: ContextId,
     pub contexts: Vec<Context>,
+    // named device specs, to create devices from.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub templates: BTreeMap<String, Value>,
     //todo : when loading, put a ref to the active context for faster access
     // to avoid looping through the contexts each time.
     // #[serde(skip)]
@@ -29,31 +47,96 @@ pub struct Context {
     pub name: ContextId,
     pub drogue_cloud_url: Url,
     pub default_app: Option<AppId>,
+    pub last_app: Option<AppId>,
     pub default_algo: Option<String>,
     pub auth_url: Url,
     pub token_url: Url,
     pub registry_url: Url,
     pub token_exp_date: DateTime<Utc>,
     pub token: BasicTokenResponse,
+    // the scopes and audience requested at login, reused when refreshing the token.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub scopes: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audience: Option<String>,
+    // the OAuth client, when it is not the default drogue client.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    // keys unknown to this version of drg, kept as they are.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, Value>,
 }
 
 impl Config {
     pub fn empty() -> Config {
         Config {
+            version: CONFIG_VERSION,
             active_context: String::new(),
             contexts: Vec::new(),
+            templates: BTreeMap::new(),
             //            active_ctx_ref: None,
         }
     }
+
+    // A config with a single context, for running without a configuration file.
+    // The token cannot be refreshed, so its expiration date is not tracked.
+    pub fn from_token(registry_url: Url, token: &str) -> Config {
+        let context = Context {
+            name: "no-config".to_string(),
+            drogue_cloud_url: registry_url.clone(),
+            default_app: None,
+            last_app: None,
+            default_algo: None,
+            auth_url: registry_url.clone(),
+            token_url: registry_url.clone(),
+            registry_url,
+            token_exp_date: Utc::now(),
+            token: BasicTokenResponse::new(
+                AccessToken::new(token.to_string()),
+                BasicTokenType::Bearer,
+                EmptyExtraTokenFields {},
+            ),
+            scopes: Vec::new(),
+            audience: None,
+            client_id: None,
+            extra: BTreeMap::new(),
+        };
+
+        Config {
+            version: CONFIG_VERSION,
+            active_context: context.name.clone(),
+            contexts: vec![context],
+            templates: BTreeMap::new(),
+        }
+    }
+
     pub fn from(path: Option<&str>) -> Result<Config> {
         let path = eval_config_path(path);
         log::info!("Loading configuration file: {}", &path);
 
-        let file = File::open(path).context(
+        let file = File::open(&path).context(
             "Unable to open configuration file. Did you log into a drogue cloud cluster ?",
         )?;
-        let config: Config =
-            serde_yaml::from_reader(file).context("Invalid configuration file.")?;
+        let raw: Value = if is_json(&path) {
+            serde_json::from_reader(file).context("Invalid configuration file.")?
+        } else {
+            serde_yaml::from_reader(file).context("Invalid configuration file.")?
+        };
+
+        let (raw, migrated) = migrate(raw)?;
+        let config: Config = serde_json::from_value(raw).context("Invalid configuration file.")?;
+
+        if migrated {
+            let backup = format!("{}.bak", path);
+            std::fs::copy(&path, &backup)
+                .context("Unable to back up the configuration file before migrating it")?;
+            config.write(Some(&path))?;
+            log::warn!(
+                "Migrated the configuration file to version {}. The previous file is saved as {}",
+                CONFIG_VERSION,
+                backup
+            );
+        }
 
         //       config.active_ctx_ref = config.get_active_context().ok();
         Ok(config)
@@ -68,7 +151,9 @@ impl Config {
             self.contexts.push(context);
             Ok(())
         } else {
-            context.default_app = self.get_context(&Some(name.clone()))?.default_app.clone();
+            let existing = self.get_context(&Some(name.clone()))?;
+            context.default_app = existing.default_app.clone();
+            context.last_app = existing.last_app.clone();
             self.replace_context(context)?;
             Ok(())
         }
@@ -129,6 +214,21 @@ impl Config {
         }
         false
     }
+
+    pub fn template(&self, name: &str) -> Result<&Value> {
+        self.templates.get(name).ok_or_else(|| {
+            anyhow!(
+                "No template {}, add it with `drg config add-template {} -f <file>`.",
+                name,
+                name
+            )
+        })
+    }
+
+    pub fn is_last_context(&self, name: &str) -> bool {
+        self.contexts.len() == 1 && self.contains_context(name)
+    }
+
     pub fn list_contexts(&self) {
         let mut table = Table::new("{:<}  {:<}  {:<}");
         table.add_row(
@@ -174,12 +274,18 @@ impl Config {
     pub fn write(&self, path: Option<&str>) -> Result<()> {
         let path = eval_config_path(path);
         if let Some(parent) = Path::new(&path).parent() {
-            create_dir_all(parent).context("Failed to create parent directory of configuration")?;
+            create_config_dir(parent)
+                .context("Failed to create parent directory of configuration")?;
         }
 
+        let content = if is_json(&path) {
+            serde_json::to_string_pretty(&self)?
+        } else {
+            serde_yaml::to_string(&self)?
+        };
+
         log::info!("Saving config file: {}", &path);
-        write(&path, serde_yaml::to_string(&self)?)
-            .context(format!("Unable to write config file :{}", path))
+        write_private(&path, content).context(format!("Unable to write config file :{}", path))
     }
 
     pub fn delete_context(&mut self, name: &str) -> Result<()> {
@@ -203,7 +309,12 @@ impl Config {
     // https://github.com/ctron/operator-framework/blob/e827775e023dfbe22a9defbf31e6a87f46d38ef5/src/install/container/env.rs#L259-L277
 
     pub fn rename_context(&mut self, name: ContextId, new_name: ContextId) -> Result<()> {
-        if self.contains_context(&name) {
+        if self.contains_context(&new_name) {
+            Err(anyhow!(
+                "Context {} already exists in config file.",
+                new_name
+            ))
+        } else if self.contains_context(&name) {
             let ctx = self.get_context_as_mut(&name)?;
             ctx.rename(new_name.clone());
 
@@ -240,15 +351,235 @@ impl Context {
     pub fn set_default_algo(&mut self, algo: SignAlgo) {
         self.default_algo = Some(algo.as_ref().to_string())
     }
+
+    // Set a key of the context, as named in the config file.
+    // The keys filled at login can only be changed with force, as can unknown keys.
+    pub fn set(&mut self, key: &str, value: &str, force: bool) -> Result<()> {
+        match key {
+            "default_app" => self.default_app = Some(value.to_string()),
+            "last_app" => self.last_app = Some(value.to_string()),
+            "default_algo" => self.set_default_algo(
+                SignAlgo::from_str(value)
+                    .map_err(|_| anyhow!("Unknown algorithm {}, expected ECDSA, EdDSA or RSA.", value))?,
+            ),
+            "registry_url" => self.registry_url = parse_url(key, value)?,
+            "drogue_cloud_url" => self.drogue_cloud_url = parse_url(key, value)?,
+            "audience" => self.audience = Some(value.to_string()),
+            "client_id" => self.client_id = Some(value.to_string()),
+            "scopes" => {
+                self.scopes = value
+                    .split(',')
+                    .map(|s| s.trim().to_string())
+                    .filter(|s| !s.is_empty())
+                    .collect()
+            }
+            "auth_url" if force => self.auth_url = parse_url(key, value)?,
+            "token_url" if force => self.token_url = parse_url(key, value)?,
+            "name" | "token" | "token_exp_date" => {
+                return Err(anyhow!(
+                    "{} cannot be set, use `drg context rename` or `drg login`.",
+                    key
+                ))
+            }
+            "auth_url" | "token_url" => {
+                return Err(anyhow!(
+                    "{} is set at login, changing it can break the context. Use --force to change it anyway.",
+                    key
+                ))
+            }
+            _ if force => {
+                self.extra.insert(key.to_string(), json!(value));
+            }
+            _ => {
+                return Err(anyhow!(
+                    "Unknown key {}. Use --force to set it anyway.",
+                    key
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    // Clear a key of the context. The urls are required, the token can only be cleared with force.
+    pub fn unset(&mut self, key: &str, force: bool) -> Result<()> {
+        match key {
+            "default_app" => self.default_app = None,
+            "last_app" => self.last_app = None,
+            "default_algo" => self.default_algo = None,
+            "audience" => self.audience = None,
+            "client_id" => self.client_id = None,
+            "scopes" => self.scopes.clear(),
+            // the context needs a new login afterwards.
+            "token" | "token_exp_date" if force => {
+                self.token = BasicTokenResponse::new(
+                    AccessToken::new(String::new()),
+                    BasicTokenType::Bearer,
+                    EmptyExtraTokenFields {},
+                );
+                self.token_exp_date = Utc::now();
+            }
+            "token" | "token_exp_date" => {
+                return Err(anyhow!(
+                    "Unsetting {} logs out of the context. Use --force to do it anyway.",
+                    key
+                ))
+            }
+            "name" | "drogue_cloud_url" | "registry_url" | "auth_url" | "token_url" => {
+                return Err(anyhow!(
+                    "{} is required and cannot be unset, use `drg context set` to change it.",
+                    key
+                ))
+            }
+            _ => {
+                self.extra
+                    .remove(key)
+                    .ok_or_else(|| anyhow!("Unknown key {}.", key))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_url(key: &str, value: &str) -> Result<Url> {
+    Url::parse(value).context(format!("Invalid url for {} : {}", key, value))
+}
+
+// The permissions of the file, when other users can access it.
+#[cfg(unix)]
+pub fn open_permissions(path: &str) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+
+    std::fs::metadata(path)
+        .ok()
+        .map(|metadata| metadata.permissions().mode() & 0o777)
+        .filter(|mode| mode & 0o077 != 0)
+}
+
+#[cfg(not(unix))]
+pub fn open_permissions(_path: &str) -> Option<u32> {
+    None
+}
+
+// Like ssh does for keys, warn when other users can access the file holding the tokens.
+#[cfg(unix)]
+pub fn check_permissions(path: Option<&str>, strict: bool) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = eval_config_path(path);
+    let mode = match open_permissions(&path) {
+        Some(mode) => mode,
+        None => return Ok(()),
+    };
+
+    if strict {
+        return Err(anyhow!(
+            "Permissions {:o} for {} are too open. Run chmod 600 {}",
+            mode,
+            path,
+            path
+        ));
+    }
+
+    eprintln!(
+        "Warning: permissions {:o} for {} are too open. The file contains tokens and should only be accessible by you.",
+        mode, path
+    );
+    if crate::util::is_interactive() && crate::util::confirm("Change them to 600 ?")? {
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+    } else {
+        eprintln!("Run chmod 600 {}", path);
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn check_permissions(_path: Option<&str>, _strict: bool) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn create_config_dir(dir: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::DirBuilderExt;
+    std::fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)
+}
+
+#[cfg(not(unix))]
+fn create_config_dir(dir: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(dir)
+}
+
+// The config file contains tokens, so only the user can read it.
+#[cfg(unix)]
+fn write_private(path: &str, content: String) -> std::io::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?
+        .write_all(content.as_bytes())
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &str, content: String) -> std::io::Result<()> {
+    std::fs::write(path, content)
+}
+
+pub fn set_local_config(enabled: bool) {
+    LOCAL_CONFIG.store(enabled, Ordering::Relaxed);
+}
+
+// The closest local config file, in `dir` or one of its ancestors, like git does.
+fn find_local_config(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .flat_map(|d| LOCAL_CONFIG_FILES.iter().map(move |f| d.join(f)))
+        .find(|p| p.is_file())
+}
+
+// A local config file holds project settings only, such as `default_app: my-app`.
+// A whole config is ignored : the tokens written back to it would end up in the project.
+fn read_project_settings(path: &Path) -> Option<Value> {
+    let content = std::fs::read_to_string(path).ok()?;
+    let raw: Value = serde_yaml::from_str(&content).ok()?;
+    let settings = raw.as_object()?;
+    if settings.contains_key("contexts") || settings.contains_key("token") {
+        log::warn!(
+            "Ignoring {}, a local config file only holds project settings such as default_app, not contexts or tokens.",
+            path.display()
+        );
+        return None;
+    }
+    Some(raw)
+}
+
+fn project_default_app_in(dir: &Path) -> Option<(AppId, PathBuf)> {
+    let path = find_local_config(dir)?;
+    let settings = read_project_settings(&path)?;
+    settings["default_app"]
+        .as_str()
+        .map(|app| (app.to_string(), path))
+}
+
+// The default app of the project in the current directory, and the file setting it.
+pub fn project_default_app() -> Option<(AppId, PathBuf)> {
+    env::current_dir()
+        .ok()
+        .filter(|_| LOCAL_CONFIG.load(Ordering::Relaxed))
+        .and_then(|dir| project_default_app_in(&dir))
 }
 
 // use the provided config path or `$DRGCFG` value if set
 // otherwise will default to $XDG_CONFIG_HOME
 // fall back to `$HOME/.config` if XDG var is not set.
-fn eval_config_path(path: Option<&str>) -> String {
+pub fn eval_config_path(path: Option<&str>) -> String {
     match path {
         Some(p) => p.to_string(),
-        None => env::var("DRGCFG").unwrap_or_else(|_| {
+        None => env::var("DRGCFG").ok().unwrap_or_else(|| {
             let xdg = match config_dir() {
                 Some(path) => path.into_os_string().into_string().unwrap(),
                 None => {
@@ -256,7 +587,154 @@ fn eval_config_path(path: Option<&str>) -> String {
                     exit(1);
                 }
             };
-            format!("{}/drg_config.yaml", xdg)
+            // YAML is the default, an existing JSON config file is used as well.
+            let json = format!("{}/drg_config.json", xdg);
+            if Path::new(&json).exists() && !Path::new(&format!("{}/drg_config.yaml", xdg)).exists()
+            {
+                json
+            } else {
+                format!("{}/drg_config.yaml", xdg)
+            }
         }),
     }
 }
+
+// Bring a config file written by an older drg to the current schema.
+fn migrate(mut raw: Value) -> Result<(Value, bool)> {
+    let version = raw["version"].as_u64().unwrap_or(0);
+    if version > CONFIG_VERSION {
+        return Err(anyhow!(
+            "The configuration file was written by a newer drg (version {}), please upgrade drg.",
+            version
+        ));
+    } else if version == CONFIG_VERSION {
+        return Ok((raw, false));
+    }
+    if !raw.is_object() {
+        return Err(anyhow!("Invalid configuration file, expected a mapping."));
+    }
+
+    // 0 to 1 : the version is added. Files from before contexts hold a single context.
+    if raw.get("contexts").is_none() {
+        let mut context = raw;
+        context["name"] = json!("default");
+        raw = json!({ "active_context": "default", "contexts": [context] });
+    }
+    raw["version"] = json!(CONFIG_VERSION);
+
+    Ok((raw, true))
+}
+
+// The config file is written back in the format it was read.
+fn is_json(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .map_or(false, |ext| ext.eq_ignore_ascii_case("json"))
+}
+
+#[cfg(test)]
+mod config_test {
+    use super::*;
+
+    #[test]
+    fn test_find_local_config_in_ancestors() {
+        let dir = tempfile::tempdir().unwrap();
+        let nested = dir.path().join("project").join("src");
+        std::fs::create_dir_all(&nested).unwrap();
+        assert_eq!(find_local_config(&nested), None);
+
+        let config = dir.path().join("project").join(".drgconfig.json");
+        std::fs::write(&config, "{}").unwrap();
+        assert_eq!(find_local_config(&nested), Some(config));
+    }
+
+    #[test]
+    fn test_migrate_rejects_non_mapping() {
+        assert!(migrate(json!("config")).is_err());
+        assert!(migrate(json!([1, 2])).is_err());
+    }
+
+    #[test]
+    fn test_project_default_app() {
+        let dir = tempfile::tempdir().unwrap();
+        let settings = dir.path().join(".drgconfig.yaml");
+        std::fs::write(&settings, "default_app: my-app\n").unwrap();
+        assert_eq!(
+            project_default_app_in(dir.path()),
+            Some(("my-app".to_string(), settings.clone()))
+        );
+
+        // a whole config is ignored, its tokens must not be written in the project.
+        std::fs::write(&settings, "active_context: foo\ncontexts: []\n").unwrap();
+        assert_eq!(project_default_app_in(dir.path()), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_written_config_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("drg").join("drg_config.yaml");
+        let path = path.to_str().unwrap();
+
+        Config::empty().write(Some(path)).unwrap();
+
+        let mode = std::fs::metadata(path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn test_set_context_keys() {
+        let url = Url::parse("https://api.drogue.cloud").unwrap();
+        let mut config = Config::from_token(url, "token");
+        let context = config.get_context_mut(&None).unwrap();
+
+        context.set("default_app", "foo", false).unwrap();
+        assert_eq!(context.default_app.as_deref(), Some("foo"));
+
+        assert!(context.set("registry_url", "not a url", false).is_err());
+        assert!(context.set("token_url", "https://sso", false).is_err());
+        assert!(context.set("unknown", "bar", false).is_err());
+
+        context.set("unknown", "bar", true).unwrap();
+        assert_eq!(context.extra["unknown"], "bar");
+    }
+
+    #[test]
+    fn test_unset_context_keys() {
+        let url = Url::parse("https://api.drogue.cloud").unwrap();
+        let mut config = Config::from_token(url, "token");
+        let context = config.get_context_mut(&None).unwrap();
+
+        context.set("default_app", "foo", false).unwrap();
+        context.unset("default_app", false).unwrap();
+        assert_eq!(context.default_app, None);
+
+        assert!(context.unset("registry_url", true).is_err());
+        assert!(context.unset("token", false).is_err());
+        assert!(context.unset("unknown", false).is_err());
+    }
+
+    #[test]
+    fn test_migrate_single_context() {
+        let (migrated, changed) =
+            migrate(json!({"drogue_cloud_url": "https://api.drogue.cloud"})).unwrap();
+
+        assert!(changed);
+        assert_eq!(migrated["version"], CONFIG_VERSION);
+        assert_eq!(migrated["active_context"], "default");
+        assert_eq!(migrated["contexts"][0]["name"], "default");
+        assert_eq!(
+            migrated["contexts"][0]["drogue_cloud_url"],
+            "https://api.drogue.cloud"
+        );
+    }
+
+    #[test]
+    fn test_migrate_current_version() {
+        let (_, changed) = migrate(json!({"version": CONFIG_VERSION, "contexts": []})).unwrap();
+        assert!(!changed);
+        assert!(migrate(json!({ "version": CONFIG_VERSION + 1 })).is_err());
+    }
+}
@@ -1,13 +1,19 @@
 use crate::config::Context;
-use crate::{util, AppId, DeviceId, Verbs};
+use crate::http::{self, Response};
+use crate::util::Output;
+use crate::{apps, cache, history, trust, util, watch, AppId, DeviceId, Outputs, Verbs};
 use anyhow::{anyhow, Context as AnyhowContext, Result};
 use json_value_merge::Merge;
-use oauth2::TokenResponse;
-use reqwest::blocking::Client;
-use reqwest::blocking::Response;
+use rand::distributions::Alphanumeric;
+use rand::rngs::OsRng;
+use rand::Rng;
 use reqwest::{StatusCode, Url};
 use serde_json::{from_str, json, Value};
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
 use std::process::exit;
+use std::time::Duration;
 use tabular::{Row, Table};
 
 fn craft_url(base: &Url, app_id: &str, device_id: Option<&str>) -> String {
@@ -29,27 +35,250 @@ pub fn delete(
     app: AppId,
     device_id: DeviceId,
     ignore_missing: bool,
+    force: bool,
 ) -> Result<()> {
-    let client = Client::new();
+    let client = http::client();
     let url = craft_url(&config.registry_url, &app, Some(&device_id));
 
-    client
-        .delete(&url)
-        .bearer_auth(&config.token.access_token().secret())
-        .send()
-        .context("Can't delete device.")
-        .map(|res| {
-            if ignore_missing && res.status() == StatusCode::NOT_FOUND {
-                exit(0);
-            } else {
-                util::print_result(res, format!("Device {}", device_id), Verbs::delete)
+    let res = http::send(config, client.delete(&url)).context("Can't delete device.")?;
+    if ignore_missing && res.status() == StatusCode::NOT_FOUND {
+        exit(0);
+    }
+    util::print_result(res, format!("Device {}", device_id), Verbs::delete);
+
+    util::finalize_deletion(config, &url, &format!("Device {}", device_id), force)
+}
+
+pub fn read(
+    config: &Context,
+    app: AppId,
+    device_id: DeviceId,
+    resolve_aliases: bool,
+    decode_psk: bool,
+    include_deleted: bool,
+    output: &Output,
+) -> Result<()> {
+    let client = http::client();
+    let url = craft_url(&config.registry_url, &app, Some(&device_id));
+    let req = client
+        .get(&url)
+        .query(&util::list_query(None, include_deleted));
+    let res = http::send(config, req).context("Can't get device.")?;
+    let mut device = match res.status() {
+        StatusCode::OK => res.json()?,
+        StatusCode::NOT_FOUND if resolve_aliases => find_alias(config, &app, &device_id)?,
+        s => util::exit_with_code(s),
+    };
+    // redacted in every output, before it is routed to a file or the clipboard.
+    show_psk(&mut device, decode_psk)?;
+    util::deletion_notice(&format!("Device {}", device_id), &device);
+
+    if output.format == Some(Outputs::pem) {
+        output.write(certificate(config, &app, &device)?)
+    } else if let Some(base) = output.save {
+        util::save(base, "json", &serde_json::to_vec_pretty(&device)?)
+    } else if output.is_default() {
+        util::show_json(device.to_string());
+        Ok(())
+    } else {
+        output.resource(&device)
+    }
+}
+
+// The certificate a device authenticates with : the trust anchor of its app, which signs the
+// device certificates. The device needs an alias with the subject of its certificate.
+fn certificate(config: &Context, app: &str, device: &Value) -> Result<String> {
+    let x509 = device["spec"]["alias"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|a| a.as_str())
+        .any(|a| a.starts_with("CN="));
+    if !x509 {
+        return Err(anyhow!(
+            "Device {} does not use X.509 certificates, it has no CN=... alias. Create it with --cert, or add the alias with `drg set alias`.",
+            util::resource_name(device)
+        ));
+    }
+
+    let pem = trust::decode_anchor(&apps::get_trust_anchor(config, app)?)?;
+    Ok(pem.trim_end().to_string())
+}
+
+// Servers reporting the connection state of the devices put it in their status,
+// as "connection": {"connected": true, "since": "<timestamp>"}.
+fn connection(device: &Value) -> Option<&Value> {
+    device["status"]
+        .get("connection")
+        .filter(|c| c["connected"].is_boolean())
+}
+
+fn connection_not_reported() -> anyhow::Error {
+    anyhow!("The connection state of the devices is not reported by this server.")
+}
+
+// Whether the device is connected, and since when.
+pub fn read_connection(
+    config: &Context,
+    app: AppId,
+    device_id: DeviceId,
+    output: &Output,
+) -> Result<()> {
+    let device = get_device(config, &app, &device_id)?;
+    let state = connection(&device).ok_or_else(connection_not_reported)?;
+
+    if !output.is_default() {
+        return output.resource(state);
+    }
+    let connected = if state["connected"] == true {
+        "connected"
+    } else {
+        "disconnected"
+    };
+    match state["since"].as_str() {
+        Some(since) => println!("Device {} is {} since {}.", device_id, connected, since),
+        None => println!("Device {} is {}.", device_id, connected),
+    }
+    Ok(())
+}
+
+fn keep_connected(devices: Vec<Value>) -> Result<Vec<Value>> {
+    if !devices.is_empty() && devices.iter().all(|d| connection(d).is_none()) {
+        return Err(connection_not_reported());
+    }
+    Ok(devices
+        .into_iter()
+        .filter(|d| connection(d).map_or(false, |c| c["connected"] == true))
+        .collect())
+}
+
+// The pre-shared keys are redacted, unless they are decoded to hexadecimal.
+fn show_psk(device: &mut Value, decode: bool) -> Result<()> {
+    let keys = device
+        .pointer_mut("/spec/credentials/credentials")
+        .and_then(|c| c.as_array_mut())
+        .into_iter()
+        .flatten()
+        .filter_map(|c| c.pointer_mut("/psk/key"));
+
+    let mut decoded = false;
+    for key in keys {
+        *key = match (decode, key.as_str()) {
+            (true, Some(k)) => {
+                decoded = true;
+                let bytes = base64::decode(k).context("Invalid pre-shared key, not base64")?;
+                Value::String(encode_hex(&bytes))
             }
+            _ => Value::String("<redacted>".to_string()),
+        };
+    }
+    if decoded {
+        eprintln!("Warning : the pre-shared keys of the device are shown, keep them secret.");
+    }
+    Ok(())
+}
+
+// Lists never show the pre-shared keys, --decode-psk only applies to a single device.
+fn redact_psk(devices: &mut [Value]) {
+    for device in devices {
+        // can't fail, nothing is decoded.
+        show_psk(device, false).ok();
+    }
+}
+
+fn find_alias(config: &Context, app: &str, device_id: &str) -> Result<Value> {
+    let device = get_list(config, app, None, false)?
+        .into_iter()
+        .find(|d| {
+            d["spec"]["alias"]
+                .as_array()
+                .map_or(false, |a| a.iter().any(|a| a == device_id.as_str()))
         })
+        .ok_or_else(|| {
+            anyhow!(
+                "No device {} nor device with the alias {} in app {}.",
+                device_id,
+                device_id,
+                app
+            )
+        })?;
+    eprintln!(
+        "The alias {} matched the device {}.",
+        device_id,
+        device["metadata"]["name"].as_str().unwrap_or_default()
+    );
+    Ok(device)
+}
+
+pub fn watch(
+    config: &Context,
+    app: AppId,
+    device_id: DeviceId,
+    interval: Duration,
+    status_only: bool,
+) -> Result<()> {
+    let url = craft_url(&config.registry_url, &app, Some(&device_id));
+    watch::watch(config, &url, interval, status_only)
+}
+
+pub fn history(config: &Context, app: AppId, device_id: DeviceId) -> Result<()> {
+    history::list(
+        config,
+        &craft_url(&config.registry_url, &app, Some(&device_id)),
+    )
+}
+
+pub fn read_revision(
+    config: &Context,
+    app: AppId,
+    device_id: DeviceId,
+    revision: u64,
+    output: &Output,
+) -> Result<()> {
+    let url = craft_url(&config.registry_url, &app, Some(&device_id));
+    history::read_revision(config, &url, revision, output)
 }
 
-pub fn read(config: &Context, app: AppId, device_id: DeviceId) -> Result<()> {
-    get(&config, &app, &device_id)
-        .map(|res| util::print_result(res, device_id.to_string(), Verbs::get))
+// Restore the spec of a previous revision, or of a backup file, keeping the current metadata.
+pub fn rollback(
+    config: &Context,
+    app: AppId,
+    device_id: DeviceId,
+    revision: Option<u64>,
+    file: Option<&str>,
+    yes: bool,
+) -> Result<()> {
+    let previous = match (revision, file) {
+        (Some(revision), _) => {
+            let url = craft_url(&config.registry_url, &app, Some(&device_id));
+            history::get_revision(config, &url, revision)?
+        }
+        (None, Some(f)) => util::get_data_from_file(f)?,
+        (None, None) => return Err(anyhow!("Missing the revision or the file to restore.")),
+    };
+
+    let res = get(config, &app, &device_id)?;
+    let mut device: Value = match res.status() {
+        StatusCode::OK => res.json()?,
+        s => util::exit_with_code(s),
+    };
+    // an empty spec would wipe the one of the device.
+    if previous["spec"].is_null() {
+        return Err(anyhow!(
+            "There is no spec to restore, the device is unchanged."
+        ));
+    }
+    device["spec"] = previous["spec"].clone();
+
+    // without a terminal to confirm, nothing is done.
+    if !yes && !util::confirm(&format!("Overwrite the spec of device {} ?", device_id))? {
+        return Err(anyhow!(
+            "Rollback cancelled. Use --yes to skip the confirmation."
+        ));
+    }
+
+    put(config, &app, &device_id, device)
+        .map(|res| util::print_result(res, format!("Device {}", device_id), Verbs::rollback))
 }
 
 pub fn create(
@@ -58,6 +287,7 @@ pub fn create(
     data: serde_json::Value,
     app_id: AppId,
     file: Option<&str>,
+    id_only: bool,
 ) -> Result<()> {
     let data = if data == json!({}) {
         json!({"credentials": {}})
@@ -78,26 +308,54 @@ pub fn create(
         }
     };
 
-    let client = Client::new();
-    let url = craft_url(&config.registry_url, &app_id, None);
+    let res = post(config, &app_id, &body)?;
+    if id_only {
+        util::print_id(res, &body, &device_id);
+    } else {
+        util::print_result(res, format!("Device {}", device_id), Verbs::create);
+    }
+    Ok(())
+}
 
-    client
+// Create a device of the app from the whole resource.
+pub fn post(config: &Context, app: &str, body: &Value) -> Result<Response> {
+    let client = http::client();
+    let url = craft_url(&config.registry_url, app, None);
+
+    let req = client
         .post(&url)
         .header(reqwest::header::CONTENT_TYPE, "application/json")
-        .bearer_auth(&config.token.access_token().secret())
-        .body(body.to_string())
-        .send()
-        .context("Can't create device.")
-        .map(|res| util::print_result(res, format!("Device {}", device_id), Verbs::create))
+        .body(body.to_string());
+
+    http::send(config, req).context("Can't create device.")
 }
 
-pub fn edit(config: &Context, app: AppId, device_id: DeviceId, file: Option<&str>) -> Result<()> {
+pub fn edit(
+    config: &Context,
+    app: AppId,
+    device_id: DeviceId,
+    file: Option<&str>,
+    resource_version: Option<&str>,
+    path: Option<&str>,
+    yes: bool,
+) -> Result<()> {
     match file {
         Some(f) => {
-            let data = util::get_data_from_file(f)?;
+            let mut data = util::get_data_from_file(f)?;
+            if let Some(version) = resource_version {
+                util::set_resource_version(&mut data, version)?;
+            }
 
-            put(&config, &app, &device_id, data)
-                .map(|res| util::print_result(res, format!("Device {}", device_id), Verbs::edit))
+            let res = put(&config, &app, &device_id, data)?;
+            if res.status() == StatusCode::CONFLICT {
+                let current = get(&config, &app, &device_id)?.json()?;
+                return Err(util::conflict_error(
+                    format!("Device {}", device_id),
+                    &current,
+                ));
+            }
+            util::print_result(res, format!("Device {}", device_id), Verbs::edit);
+            Ok(())
         }
         None => {
             //read device data
@@ -106,7 +364,7 @@ pub fn edit(config: &Context, app: AppId, device_id: DeviceId, file: Option<&str
                 Ok(r) => match r.status() {
                     StatusCode::OK => {
                         let body = r.text().unwrap_or_else(|_| "{}".to_string());
-                        let insert = util::editor(body)?;
+                        let insert = util::editor(body, path, yes)?;
                         put(&config, &app, &device_id, insert).map(|p| {
                             util::print_result(p, format!("Device {}", device_id), Verbs::edit)
                         })
@@ -125,24 +383,97 @@ pub fn edit(config: &Context, app: AppId, device_id: DeviceId, file: Option<&str
     }
 }
 
-pub fn list(config: &Context, app: AppId, labels: Option<String>) -> Result<()> {
-    let client = Client::new();
-    let url = craft_url(&config.registry_url, &app, None);
+pub fn list(
+    config: &Context,
+    app: AppId,
+    labels: Option<String>,
+    include_deleted: bool,
+    only_connected: bool,
+    output: &Output,
+) -> Result<()> {
+    if output.is_streamed() {
+        let url = craft_url(&config.registry_url, &app, None);
+        let query = util::list_query(labels, include_deleted);
+        return util::list_paginated(config, &url, &query, output.chunk_size, |page| {
+            let page = if only_connected {
+                keep_connected(page)?
+            } else {
+                page
+            };
+            let mut page = output.filter(page);
+            redact_psk(&mut page);
+            output.list(&page)
+        });
+    }
 
-    let mut req = client
-        .get(&url)
-        .bearer_auth(&config.token.access_token().secret());
+    let devices = match output.chunk_size {
+        Some(size) => {
+            let url = craft_url(&config.registry_url, &app, None);
+            let query = util::list_query(labels, include_deleted);
+            util::list_all(config, &url, &query, Some(size))?
+        }
+        None => get_list(config, &app, labels, include_deleted)?,
+    };
+    let devices = if only_connected {
+        keep_connected(devices)?
+    } else {
+        devices
+    };
+    let mut devices = output.filter(devices);
+    output.sort(&mut devices);
+    redact_psk(&mut devices);
+    if output.is_default() {
+        pretty_list(devices, include_deleted, output.full_timestamps, false)
+    } else {
+        output.list(&devices)
+    }
+}
 
-    if let Some(labels) = labels {
-        req = req.query(&[("labels", labels)]);
+// List the devices of several apps together. The devices tell which app they belong to,
+// in their metadata, and in an APP column of the default table.
+pub fn list_across(
+    config: &Context,
+    apps: Vec<AppId>,
+    labels: Option<String>,
+    include_deleted: bool,
+    only_connected: bool,
+    output: &Output,
+) -> Result<()> {
+    let mut devices = Vec::new();
+    for app in apps {
+        devices.extend(get_list(config, &app, labels.clone(), include_deleted)?);
+    }
+    if only_connected {
+        devices = keep_connected(devices)?;
+    }
+    let mut devices = output.filter(devices);
+    output.sort(&mut devices);
+    redact_psk(&mut devices);
+    if output.is_default() {
+        pretty_list(devices, include_deleted, output.full_timestamps, true)
+    } else {
+        output.list(&devices)
     }
+}
+
+pub fn get_list(
+    config: &Context,
+    app: &str,
+    labels: Option<String>,
+    include_deleted: bool,
+) -> Result<Vec<Value>> {
+    let client = http::client();
+    let url = craft_url(&config.registry_url, app, None);
+
+    let req = client
+        .get(&url)
+        .query(&util::list_query(labels, include_deleted));
 
-    let res = req.send().context("Can't list devices");
+    let res = http::send(config, req).context("Can't list devices");
 
     if let Ok(r) = res {
         if r.status() == StatusCode::OK {
-            pretty_list(r.text()?)?;
-            Ok(())
+            from_str(r.text()?.as_str()).context("Invalid devices list.")
         } else {
             Err(anyhow!("List operation failed with {}", r.status()))
         }
@@ -151,6 +482,27 @@ pub fn list(config: &Context, app: AppId, labels: Option<String>) -> Result<()>
     }
 }
 
+// The names of the devices of the app matching the label selector, reused for a few seconds.
+pub fn names(config: &Context, app: &str, labels: Option<String>) -> Result<Vec<String>> {
+    let key = format!(
+        "{}?labels={}",
+        craft_url(&config.registry_url, app, None),
+        labels.as_deref().unwrap_or_default()
+    );
+    if let Some(names) = cache::load_names(&key) {
+        return Ok(names);
+    }
+
+    let names: Vec<String> = get_list(config, app, labels, false)?
+        .iter()
+        .map(util::resource_name)
+        .collect();
+    if let Err(e) = cache::store_names(&key, &names) {
+        log::debug!("Could not cache the device names : {}", e);
+    }
+    Ok(names)
+}
+
 pub fn set_gateway(
     config: &Context,
     app: AppId,
@@ -191,6 +543,95 @@ pub fn set_password(
     set(config, app, device_id, data)
 }
 
+// The credentials given as flags when creating a device.
+pub fn credentials(password: Option<String>, psk: Option<&str>) -> Result<Vec<Value>> {
+    let mut credentials = Vec::new();
+    if let Some(password) = password {
+        credentials.push(json!({ "pass": password }));
+    }
+    if let Some(psk) = psk {
+        let key = decode_hex(psk).context("The pre-shared key must be hexadecimal")?;
+        credentials.push(json!({"psk": {"key": base64::encode(key)}}));
+    }
+    Ok(credentials)
+}
+
+fn decode_hex(hex: &str) -> Result<Vec<u8>> {
+    let hex = hex.trim_start_matches("0x");
+    if hex.is_empty() || hex.len() % 2 != 0 || !hex.is_ascii() {
+        return Err(anyhow!("Invalid hexadecimal value {}", hex));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(anyhow::Error::from))
+        .collect()
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Write each device to its own file in `dir`, named after the template.
+// Nothing is written if two devices would get the same file name.
+pub fn export(app: &str, devices: Vec<Value>, dir: &str, template: &str) -> Result<()> {
+    let mut files = Vec::new();
+    let mut names = HashSet::new();
+    for device in &devices {
+        let name = util::expand_template(template, device)?;
+        if name.contains('/') || name == "." || name == ".." {
+            return Err(anyhow!(
+                "Invalid file name {} for {}",
+                name,
+                util::resource_name(device)
+            ));
+        }
+        if !names.insert(name.clone()) {
+            return Err(anyhow!(
+                "Several devices of app {} would be written to {}, use a more specific template.",
+                app,
+                name
+            ));
+        }
+        files.push(Path::new(dir).join(name));
+    }
+
+    fs::create_dir_all(dir).context(format!("Cannot create the directory {}", dir))?;
+    for (device, file) in devices.iter().zip(&files) {
+        util::write_file_atomic(
+            file.to_str().unwrap_or_default(),
+            &serde_json::to_string_pretty(device)?,
+        )?;
+    }
+    println!("{} device(s) exported to {}", files.len(), dir);
+    Ok(())
+}
+
+pub fn get_device(config: &Context, app: &str, device_id: &str) -> Result<Value> {
+    let res = get(config, app, &device_id.to_string())?;
+    match res.status() {
+        StatusCode::OK => res.json(),
+        s => util::exit_with_code(s),
+    }
+}
+
+// Like get_device, but a missing or forbidden device is an error, so that bulk operations go on.
+fn try_get_device(config: &Context, app: &str, device_id: &str) -> Result<Value> {
+    let res = get(config, app, &device_id.to_string())?;
+    match res.status() {
+        StatusCode::OK => res.json(),
+        s => Err(anyhow!("Device {} could not be read : {}", device_id, s)),
+    }
+}
+
+// Random alphanumeric password, from the OS random number generator.
+pub fn generate_password(length: usize) -> String {
+    OsRng
+        .sample_iter(&Alphanumeric)
+        .take(length)
+        .map(char::from)
+        .collect()
+}
+
 pub fn add_alias(
     config: &Context,
     app: AppId,
@@ -207,6 +648,31 @@ pub fn add_alias(
     set(config, app, device_id, data)
 }
 
+// Change some keys of a metadata map of the device, such as its labels, and write it back.
+// Unlike print_result, failures are returned so that the other devices can still be updated.
+pub fn update_metadata(
+    config: &Context,
+    app: &AppId,
+    device_id: &DeviceId,
+    field: &str,
+    changes: &[(String, Option<String>)],
+) -> Result<()> {
+    let mut device = try_get_device(config, app, device_id)?;
+    util::apply_metadata_changes(&mut device["metadata"][field], changes);
+
+    let res = put(config, app, device_id, device)?;
+    if res.status().is_success() {
+        println!("Device {} updated.", device_id);
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "Device {} could not be updated : {}",
+            device_id,
+            res.status()
+        ))
+    }
+}
+
 // The "set" operation merges the data with what already exists on the server side
 fn set(config: &Context, app: AppId, device_id: DeviceId, data: Value) -> Result<()> {
     //read device data
@@ -233,14 +699,10 @@ fn set(config: &Context, app: AppId, device_id: DeviceId, data: Value) -> Result
 }
 
 fn get(config: &Context, app: &str, device_id: &DeviceId) -> Result<Response> {
-    let client = Client::new();
+    let client = http::client();
     let url = craft_url(&config.registry_url, app, Some(&device_id));
 
-    client
-        .get(&url)
-        .bearer_auth(&config.token.access_token().secret())
-        .send()
-        .context("Can't get device.")
+    http::send(config, client.get(&url)).context("Can't get device.")
 }
 
 fn put(
@@ -249,41 +711,94 @@ fn put(
     device_id: &DeviceId,
     data: serde_json::Value,
 ) -> Result<Response> {
-    let client = Client::new();
+    let client = http::client();
     let url = craft_url(&config.registry_url, app, Some(&device_id));
-    let token = &config.token.access_token().secret();
 
-    client
+    let req = client
         .put(&url)
         .header(reqwest::header::CONTENT_TYPE, "application/json")
-        .bearer_auth(token)
-        .body(data.to_string())
-        .send()
-        .context(format!(
-            "Error while updating device data for {}",
-            device_id
-        ))
+        .body(data.to_string());
+
+    http::send(config, req).context(format!(
+        "Error while updating device data for {}",
+        device_id
+    ))
 }
 
 // todo drogue-client and the types would be useful for this
-fn pretty_list(data: String) -> Result<()> {
-    let device_array: Vec<Value> = from_str(data.as_str())?;
-
-    let mut table = Table::new("{:<} {:<}");
-    table.add_row(Row::new().with_cell("NAME").with_cell("AGE"));
+fn pretty_list(
+    device_array: Vec<Value>,
+    include_deleted: bool,
+    full_timestamps: bool,
+    app_column: bool,
+) -> Result<()> {
+    let columns = 2 + include_deleted as usize + app_column as usize;
+    let mut table = Table::new(&vec!["{:<}"; columns].join(" "));
+    let mut header = Row::new();
+    if app_column {
+        header.add_cell("APP");
+    }
+    header.add_cell("NAME");
+    header.add_cell(if full_timestamps { "CREATED" } else { "AGE" });
+    if include_deleted {
+        header.add_cell("DELETION");
+    }
+    table.add_row(header);
 
     for dev in device_array {
         let name = dev["metadata"]["name"].as_str();
         let creation = dev["metadata"]["creationTimestamp"].as_str();
         if let Some(name) = name {
-            table.add_row(
-                Row::new()
-                    .with_cell(name)
-                    .with_cell(util::age(creation.unwrap())?),
-            );
+            let mut row = Row::new();
+            if app_column {
+                row.add_cell(dev["metadata"]["application"].as_str().unwrap_or_default());
+            }
+            row.add_cell(name);
+            row.add_cell(util::timestamp(creation.unwrap(), full_timestamps)?);
+            if include_deleted {
+                row.add_cell(util::deletion_age(&dev)?);
+            }
+            table.add_row(row);
         }
     }
 
     print!("{}", table);
     Ok(())
 }
+
+#[cfg(test)]
+mod devices_test {
+    use super::*;
+
+    fn psk_device() -> Value {
+        json!({"spec": {"credentials": {"credentials": [{"psk": {"key": "AAEC"}}]}}})
+    }
+
+    // whatever the output is, a file or the clipboard, it gets the device as changed here.
+    #[test]
+    fn test_psk_redacted() {
+        let mut device = psk_device();
+        show_psk(&mut device, false).unwrap();
+        assert_eq!(
+            device["spec"]["credentials"]["credentials"][0]["psk"]["key"],
+            "<redacted>"
+        );
+
+        let mut devices = vec![psk_device()];
+        redact_psk(&mut devices);
+        assert_eq!(
+            devices[0]["spec"]["credentials"]["credentials"][0]["psk"]["key"],
+            "<redacted>"
+        );
+    }
+
+    #[test]
+    fn test_psk_decoded() {
+        let mut device = psk_device();
+        show_psk(&mut device, true).unwrap();
+        assert_eq!(
+            device["spec"]["credentials"]["credentials"][0]["psk"]["key"],
+            "000102"
+        );
+    }
+}
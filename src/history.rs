@@ -0,0 +1,68 @@
+use crate::config::Context;
+use crate::http;
+use crate::util::{self, Output};
+use anyhow::{anyhow, Context as AnyhowContext, Result};
+use reqwest::StatusCode;
+use serde_json::Value;
+use tabular::{Row, Table};
+
+// Servers keeping the history of a resource list its revisions under <resource>/revisions,
+// as objects holding the revision number, its timestamp and the resource as it was.
+fn revisions(config: &Context, url: &str) -> Result<Vec<Value>> {
+    let client = http::client();
+    let res = http::send(config, client.get(&format!("{}/revisions", url)))
+        .context("Can't retrieve the revisions.")?;
+
+    match res.status() {
+        StatusCode::OK => res.json(),
+        StatusCode::NOT_FOUND => {
+            // tell a missing resource apart from a server without history.
+            let resource = http::send(config, client.get(url))?;
+            if resource.status() == StatusCode::OK {
+                Err(not_supported())
+            } else {
+                util::exit_with_code(resource.status())
+            }
+        }
+        StatusCode::METHOD_NOT_ALLOWED | StatusCode::NOT_IMPLEMENTED => Err(not_supported()),
+        s => util::exit_with_code(s),
+    }
+}
+
+fn not_supported() -> anyhow::Error {
+    anyhow!("Revision history is not supported by this server.")
+}
+
+pub fn list(config: &Context, url: &str) -> Result<()> {
+    let mut table = Table::new("{:<} {:<}");
+    table.add_row(Row::new().with_cell("REVISION").with_cell("TIMESTAMP"));
+
+    for revision in revisions(config, url)? {
+        table.add_row(
+            Row::new()
+                .with_cell(&revision["revision"])
+                .with_cell(revision["timestamp"].as_str().unwrap_or_default()),
+        );
+    }
+
+    print!("{}", table);
+    Ok(())
+}
+
+pub fn get_revision(config: &Context, url: &str, revision: u64) -> Result<Value> {
+    revisions(config, url)?
+        .into_iter()
+        .find(|r| r["revision"].as_u64() == Some(revision))
+        .map(|r| r["resource"].clone())
+        .ok_or_else(|| anyhow!("Revision {} not found.", revision))
+}
+
+pub fn read_revision(config: &Context, url: &str, revision: u64, output: &Output) -> Result<()> {
+    let resource = get_revision(config, url, revision)?;
+    if output.is_default() {
+        util::show_json(resource.to_string());
+        Ok(())
+    } else {
+        output.resource(&resource)
+    }
+}
@@ -0,0 +1,611 @@
+use crate::audit;
+use crate::cache;
+use crate::config::{Config, Context};
+use crate::openid;
+use crate::util;
+
+use anyhow::{anyhow, Context as AnyhowContext, Result};
+use chrono::{DateTime, Utc};
+use oauth2::basic::BasicTokenResponse;
+use oauth2::TokenResponse;
+use once_cell::sync::{Lazy, OnceCell};
+use rand::Rng;
+use reqwest::blocking::{Client, ClientBuilder, RequestBuilder};
+use reqwest::header::{
+    HeaderMap, HeaderName, HeaderValue, AUTHORIZATION, CONTENT_TYPE, COOKIE, DATE, RETRY_AFTER,
+    SET_COOKIE,
+};
+use reqwest::{Method, StatusCode};
+use serde::de::DeserializeOwned;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// How many times a rate limited request is sent again before giving up.
+const MAX_RATE_LIMIT_RETRIES: usize = 5;
+// Used when the server does not say how long to wait.
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(1);
+// The longest wait between retries, whatever the server asks.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+// Above this difference with the server clock, in seconds, the token expiry checks can't be trusted.
+const MAX_CLOCK_SKEW: i64 = 60;
+// The blocking client gives up after this when no timeout is set.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Settings shared by all the requests made during a command.
+#[derive(Default)]
+pub struct Settings {
+    pub config_path: Option<String>,
+    // append the HTTP exchanges to this file.
+    pub dump_http: Option<String>,
+    // added to every request.
+    pub headers: HeaderMap,
+    // save the responses of GET requests, for offline use.
+    pub cache: bool,
+    // answer GET requests from the cache instead of the network.
+    pub offline: bool,
+    // replaces the default drg/<version> user agent.
+    pub user_agent: Option<String>,
+    // for the whole request, and for connecting only. Zero means no timeout.
+    pub timeout: Option<Duration>,
+    pub connect_timeout: Option<Duration>,
+    // print the requests as curl commands.
+    pub curl: bool,
+    // append a line for each request changing a resource to this file.
+    pub audit_file: Option<String>,
+    // no request is sent after it, and the requests in flight are given up when it is reached.
+    pub deadline: Option<Instant>,
+}
+
+static SETTINGS: OnceCell<Settings> = OnceCell::new();
+
+static DUMP_LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+// The clock is compared with the server's once per command.
+static CLOCK_CHECKED: AtomicBool = AtomicBool::new(false);
+
+// The token obtained if the token of the context had to be refreshed while running the command.
+static REFRESHED_TOKEN: Lazy<Mutex<Option<BasicTokenResponse>>> = Lazy::new(|| Mutex::new(None));
+
+// A response with its body already read.
+pub struct Response {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Vec<u8>,
+}
+
+impl Response {
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.body
+    }
+
+    pub fn text(&self) -> Result<String> {
+        String::from_utf8(self.body.clone()).context("The response body is not valid UTF-8")
+    }
+
+    // The extension of a file holding the body, from its content type.
+    pub fn extension(&self) -> &'static str {
+        let content_type = self
+            .headers
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.split(';').next())
+            .unwrap_or_default()
+            .trim()
+            .to_lowercase();
+        match content_type.as_str() {
+            "application/json" => "json",
+            t if t.ends_with("+json") => "json",
+            "application/x-pem-file"
+            | "application/pem-certificate-chain"
+            | "application/x-x509-ca-cert" => "pem",
+            _ => "bin",
+        }
+    }
+
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T> {
+        serde_json::from_slice(&self.body).context("The response body is not valid JSON")
+    }
+}
+
+pub fn init(settings: Settings) {
+    if SETTINGS.set(settings).is_err() {
+        log::debug!("HTTP settings are already initialized");
+    }
+}
+
+fn settings() -> &'static Settings {
+    SETTINGS.get_or_init(Settings::default)
+}
+
+// Parse a header given as `Name: Value`.
+pub fn parse_header(header: &str) -> Result<(HeaderName, HeaderValue)> {
+    let (name, value) = header
+        .split_once(':')
+        .ok_or_else(|| anyhow!("Invalid header \"{}\", expected Name: Value", header))?;
+
+    Ok((
+        HeaderName::from_bytes(name.trim().as_bytes())
+            .context(format!("Invalid header name \"{}\"", name.trim()))?,
+        HeaderValue::from_str(value.trim())
+            .context(format!("Invalid value for header {}", name.trim()))?,
+    ))
+}
+
+// A W3C traceparent header, sent with all the requests of the command, and its trace id.
+pub fn traceparent() -> (String, HeaderValue) {
+    let mut rng = rand::thread_rng();
+    let mut hex = |len: usize| -> String {
+        (0..len)
+            .map(|_| format!("{:02x}", rng.gen::<u8>()))
+            .collect()
+    };
+    let trace_id = hex(16);
+    let parent_id = hex(8);
+
+    let value = HeaderValue::from_str(&format!("00-{}-{}-01", trace_id, parent_id))
+        .expect("Invalid traceparent header");
+    (trace_id, value)
+}
+
+// A client builder with the settings shared by all the clients.
+pub fn builder() -> ClientBuilder {
+    let user_agent = settings()
+        .user_agent
+        .clone()
+        .unwrap_or_else(|| format!("drg/{}", util::VERSION));
+    let mut builder = Client::builder().user_agent(user_agent);
+    // without a call to timeout, the blocking client still gives up after 30s.
+    match settings().timeout {
+        Some(timeout) if timeout == Duration::from_secs(0) => builder = builder.timeout(None),
+        Some(timeout) => builder = builder.timeout(timeout),
+        None => {}
+    }
+    if let Some(timeout) = settings()
+        .connect_timeout
+        .filter(|t| *t != Duration::from_secs(0))
+    {
+        builder = builder.connect_timeout(timeout);
+    }
+    builder
+}
+
+pub fn client() -> Client {
+    builder().build().expect("Cannot build the HTTP client")
+}
+
+// Send an authenticated request and read the response.
+pub fn send(context: &Context, request: RequestBuilder) -> Result<Response> {
+    let cache_url = cacheable_url(&request);
+    if let (true, Some(url)) = (settings().offline, &cache_url) {
+        return Ok(Response {
+            status: StatusCode::OK,
+            headers: HeaderMap::new(),
+            body: cache::load(url)?,
+        });
+    }
+
+    let res = send_stream(context, request)?;
+
+    let response = Response {
+        status: res.status(),
+        headers: res.headers().clone(),
+        body: res.bytes()?.to_vec(),
+    };
+    if settings().dump_http.is_some() {
+        dump(format!("{}\n\n", redact_body(&response.body)));
+    }
+    if let (true, Some(url), StatusCode::OK) = (settings().cache, &cache_url, response.status) {
+        if let Err(e) = cache::store(url, &response.body) {
+            log::warn!("Could not cache the response of {} : {}", url, e);
+        }
+    }
+
+    Ok(response)
+}
+
+// Only the responses of GET requests are cached.
+fn cacheable_url(request: &RequestBuilder) -> Option<String> {
+    request
+        .try_clone()?
+        .build()
+        .ok()
+        .filter(|r| r.method() == Method::GET)
+        .map(|r| r.url().to_string())
+}
+
+// Send an authenticated request, leaving the body of the response to be read by the caller.
+// Requests changing a resource are recorded in the --audit-file.
+pub fn send_stream(
+    context: &Context,
+    request: RequestBuilder,
+) -> Result<reqwest::blocking::Response> {
+    if settings().offline {
+        return Err(anyhow!(
+            "drg is offline, only the responses cached with --cache can be read."
+        ));
+    }
+    let request = request.headers(settings().headers.clone());
+    let changing = request
+        .try_clone()
+        .and_then(|r| r.build().ok())
+        .filter(|r| !r.method().is_safe())
+        .map(|r| (r.method().clone(), r.url().clone()));
+    if changing.is_some() {
+        cache::invalidate_names();
+    }
+
+    let res = send_authenticated(context, request);
+    if let (Some(path), Some((method, url))) = (&settings().audit_file, changing) {
+        audit::record(path, context, &method, &url, &res);
+    }
+    res
+}
+
+// If the token expired while running the command, it is refreshed and the request sent again, once.
+fn send_authenticated(
+    context: &Context,
+    request: RequestBuilder,
+) -> Result<reqwest::blocking::Response> {
+    let retry = request.try_clone();
+    let token = current_token(context);
+
+    let res = execute(request.bearer_auth(token.access_token().secret()))?;
+
+    match (res.status(), retry) {
+        (StatusCode::UNAUTHORIZED, Some(retry)) => {
+            log::info!("Request unauthorized, refreshing the token and trying again.");
+            let token = refresh_token(context, &token)?;
+            execute(retry.bearer_auth(token.access_token().secret()))
+        }
+        _ => Ok(res),
+    }
+}
+
+// Execute a request, waiting as long as the server asks when it is rate limited.
+fn execute(mut request: RequestBuilder) -> Result<reqwest::blocking::Response> {
+    let mut retries = 0;
+    loop {
+        let retry = request.try_clone();
+        let res = execute_once(request)?;
+
+        match retry {
+            Some(retry)
+                if res.status() == StatusCode::TOO_MANY_REQUESTS
+                    && retries < MAX_RATE_LIMIT_RETRIES =>
+            {
+                let wait = retry_after(res.headers());
+                log::info!(
+                    "Rate limited by the server, waiting {}s before trying again.",
+                    wait.as_secs()
+                );
+                std::thread::sleep(wait);
+                retries += 1;
+                request = retry;
+            }
+            _ => return Ok(res),
+        }
+    }
+}
+
+// Retry-After is either a number of seconds or an HTTP date.
+fn retry_after(headers: &HeaderMap) -> Duration {
+    headers
+        .get(RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| {
+            v.trim()
+                .parse::<u64>()
+                .map(Duration::from_secs)
+                .ok()
+                .or_else(|| {
+                    DateTime::parse_from_rfc2822(v.trim())
+                        .ok()
+                        .and_then(|date| (date.with_timezone(&Utc) - Utc::now()).to_std().ok())
+                })
+        })
+        .unwrap_or(DEFAULT_RETRY_AFTER)
+        .min(MAX_RETRY_AFTER)
+}
+
+// Whether the --max-time of the command is reached. Loops stop sending requests then.
+pub fn deadline_exceeded() -> bool {
+    settings().deadline.map_or(false, |d| Instant::now() >= d)
+}
+
+// Whether only the cached responses can be read, see --offline.
+pub fn is_offline() -> bool {
+    settings().offline
+}
+
+pub fn check_deadline() -> Result<()> {
+    if deadline_exceeded() {
+        Err(anyhow!("The --max-time of the command is exceeded."))
+    } else {
+        Ok(())
+    }
+}
+
+// The timeout of a request, shortened to end with the --max-time of the command.
+fn request_timeout(deadline: Instant) -> Duration {
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    match settings().timeout {
+        Some(timeout) if timeout == Duration::from_secs(0) => remaining,
+        Some(timeout) => timeout.min(remaining),
+        None => DEFAULT_TIMEOUT.min(remaining),
+    }
+}
+
+fn execute_once(mut request: RequestBuilder) -> Result<reqwest::blocking::Response> {
+    check_deadline()?;
+    if let Some(deadline) = settings().deadline {
+        request = request.timeout(request_timeout(deadline));
+    }
+    if settings().curl {
+        if let Some(Ok(req)) = request.try_clone().map(|r| r.build()) {
+            eprintln!("{}", curl_command(&req));
+        }
+    }
+    if settings().dump_http.is_some() {
+        if let Some(Ok(req)) = request.try_clone().map(|r| r.build()) {
+            let body = req
+                .body()
+                .and_then(|b| b.as_bytes())
+                .map(redact_body)
+                .unwrap_or_default();
+            dump(format!(
+                "> {} {}\n{}>\n{}\n\n",
+                req.method(),
+                req.url(),
+                dump_headers(">", req.headers()),
+                body
+            ));
+        }
+    }
+
+    let res = request.send()?;
+    check_clock_skew(res.headers());
+    if settings().dump_http.is_some() {
+        dump(format!(
+            "< {}\n{}<\n",
+            res.status(),
+            dump_headers("<", res.headers())
+        ));
+    }
+    Ok(res)
+}
+
+// Seconds the local clock is ahead of the server's, from the Date header of a response.
+pub fn clock_skew(headers: &HeaderMap) -> Option<i64> {
+    headers
+        .get(DATE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| DateTime::parse_from_rfc2822(v.trim()).ok())
+        .map(|date| (Utc::now() - date.with_timezone(&Utc)).num_seconds())
+}
+
+pub fn is_clock_skewed(skew: i64) -> bool {
+    skew.abs() > MAX_CLOCK_SKEW
+}
+
+// A wrong local clock makes valid tokens look expired, or the other way around,
+// so warn when it's too far from the Date of the server.
+fn check_clock_skew(headers: &HeaderMap) {
+    let skew = match clock_skew(headers) {
+        Some(skew) => skew,
+        None => return,
+    };
+    if CLOCK_CHECKED.swap(true, Ordering::Relaxed) {
+        return;
+    }
+
+    log::debug!("Clock skew with the server : {}s", skew);
+    if is_clock_skewed(skew) {
+        eprintln!(
+            "Warning: the local clock is {}s {} the server's. Token expiration checks may fail, please sync your clock.",
+            skew.abs(),
+            if skew > 0 { "ahead of" } else { "behind" }
+        );
+    }
+}
+
+fn dump_headers(prefix: &str, headers: &HeaderMap) -> String {
+    headers
+        .iter()
+        .map(|(name, value)| format!("{} {}: {}\n", prefix, name, header_value(name, value)))
+        .collect()
+}
+
+// Secrets are redacted.
+fn header_value<'a>(name: &HeaderName, value: &'a HeaderValue) -> &'a str {
+    if name == AUTHORIZATION || name == COOKIE || name == SET_COOKIE {
+        "<redacted>"
+    } else {
+        value.to_str().unwrap_or("<binary>")
+    }
+}
+
+// The credentials of devices, in JSON bodies, are redacted like the tokens.
+fn redact_body(body: &[u8]) -> String {
+    match serde_json::from_slice::<serde_json::Value>(body) {
+        Ok(mut json) => {
+            redact_credentials(&mut json);
+            json.to_string()
+        }
+        Err(_) => String::from_utf8_lossy(body).to_string(),
+    }
+}
+
+fn redact_credentials(value: &mut serde_json::Value) {
+    use serde_json::Value;
+
+    let redacted = || Value::String("<redacted>".to_string());
+    match value {
+        Value::Object(map) => {
+            for (name, value) in map.iter_mut() {
+                match (name.as_str(), value) {
+                    ("pass" | "password", value) => *value = redacted(),
+                    ("psk", Value::Object(psk)) if psk.contains_key("key") => {
+                        psk.insert("key".to_string(), redacted());
+                    }
+                    (_, value) => redact_credentials(value),
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact_credentials),
+        _ => {}
+    }
+}
+
+// The curl command sending the same request, to share a reproduction.
+fn curl_command(request: &reqwest::blocking::Request) -> String {
+    let mut command = format!(
+        "curl -X {} {}",
+        request.method(),
+        util::shell_quote(request.url().as_str())
+    );
+    for (name, value) in request.headers() {
+        command.push_str(&format!(
+            " -H {}",
+            util::shell_quote(&format!("{}: {}", name, header_value(name, value)))
+        ));
+    }
+    if let Some(body) = request.body().and_then(|b| b.as_bytes()) {
+        command.push_str(&format!(
+            " --data {}",
+            util::shell_quote(&String::from_utf8_lossy(body))
+        ));
+    }
+    command
+}
+
+// Append to the --dump-http file. Failing to do so does not fail the command.
+fn dump(content: String) {
+    if let Some(path) = &settings().dump_http {
+        let _lock = DUMP_LOCK.lock().unwrap();
+        let written = open_dump(path).and_then(|mut f| f.write_all(content.as_bytes()));
+
+        if let Err(e) = written {
+            log::warn!("Could not write the HTTP exchange to {} : {}", path, e);
+        }
+    }
+}
+
+// The exchanges may hold private data, so only the user can read the file, like the config file.
+#[cfg(unix)]
+fn open_dump(path: &str) -> std::io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .mode(0o600)
+        .open(path)
+}
+
+#[cfg(not(unix))]
+fn open_dump(path: &str) -> std::io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+pub fn current_token(context: &Context) -> BasicTokenResponse {
+    REFRESHED_TOKEN
+        .lock()
+        .unwrap()
+        .clone()
+        .unwrap_or_else(|| context.token.clone())
+}
+
+fn refresh_token(context: &Context, rejected: &BasicTokenResponse) -> Result<BasicTokenResponse> {
+    let mut refreshed = REFRESHED_TOKEN.lock().unwrap();
+
+    // another request may have refreshed the token in the meantime.
+    if let Some(token) = refreshed.as_ref() {
+        if token.access_token().secret() != rejected.access_token().secret() {
+            return Ok(token.clone());
+        }
+    }
+
+    let mut context = context.clone();
+    context.token = rejected.clone();
+    openid::refresh_token(&mut context).map_err(|e| {
+        anyhow!(
+            "The token expired and could not be refreshed, please log in again. {}",
+            e
+        )
+    })?;
+
+    if let Err(e) = save_token(&context) {
+        log::warn!("Could not save the refreshed token : {}", e);
+    }
+
+    *refreshed = Some(context.token.clone());
+    Ok(context.token)
+}
+
+fn save_token(context: &Context) -> Result<()> {
+    let path = settings().config_path.as_deref();
+    let mut config = Config::from(path)?;
+
+    let saved = config.get_context_mut(&Some(context.name.clone()))?;
+    saved.token = context.token.clone();
+    saved.token_exp_date = context.token_exp_date;
+
+    config.write(path)
+}
+
+#[cfg(test)]
+mod http_test {
+    use super::*;
+
+    fn retry_headers(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_retry_after_seconds() {
+        assert_eq!(retry_after(&retry_headers("3")), Duration::from_secs(3));
+        assert_eq!(retry_after(&retry_headers("3600")), MAX_RETRY_AFTER);
+        assert_eq!(retry_after(&retry_headers("soon")), DEFAULT_RETRY_AFTER);
+        assert_eq!(retry_after(&HeaderMap::new()), DEFAULT_RETRY_AFTER);
+    }
+
+    #[test]
+    fn test_redact_body() {
+        let device = br#"{"spec":{"credentials":{"credentials":[{"pass":"foo"},{"user":{"username":"u","password":"bar"}},{"psk":{"key":"AAEC"}}]}}}"#;
+        let redacted: serde_json::Value = serde_json::from_str(&redact_body(device)).unwrap();
+        assert_eq!(
+            redacted,
+            serde_json::json!({"spec": {"credentials": {"credentials": [
+                {"pass": "<redacted>"},
+                {"user": {"username": "u", "password": "<redacted>"}},
+                {"psk": {"key": "<redacted>"}}
+            ]}}})
+        );
+        assert_eq!(redact_body(b"not json"), "not json");
+    }
+
+    #[test]
+    fn test_retry_after_date() {
+        let in_a_while = (Utc::now() + chrono::Duration::seconds(30)).to_rfc2822();
+        let wait = retry_after(&retry_headers(&in_a_while));
+        assert!(wait > Duration::from_secs(25) && wait <= Duration::from_secs(30));
+
+        let later = (Utc::now() + chrono::Duration::hours(2)).to_rfc2822();
+        assert_eq!(retry_after(&retry_headers(&later)), MAX_RETRY_AFTER);
+
+        let past = (Utc::now() - chrono::Duration::seconds(30)).to_rfc2822();
+        assert_eq!(retry_after(&retry_headers(&past)), DEFAULT_RETRY_AFTER);
+    }
+}
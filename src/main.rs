@@ -1,16 +1,26 @@
 mod apps;
 mod arguments;
+mod audit;
+mod cache;
 mod command;
+mod completion;
 mod config;
 mod devices;
+mod history;
+mod http;
+mod manpage;
 mod openid;
+mod schema;
+mod status;
 mod stream;
+mod tree;
 mod trust;
 mod util;
+mod watch;
 
 use arguments::{
-    Context_subcommands, Other_commands, Other_flags, Parameters, Resources, Set_args, Set_targets,
-    Trust_subcommands, Verbs,
+    Context_subcommands, Member_roles, Other_commands, Other_flags, Outputs, Parameters, Resources,
+    Roles, Set_args, Set_targets, Trust_subcommands, Verbs,
 };
 
 use crate::config::{Config, ContextId};
@@ -19,6 +29,7 @@ use json_value_merge::Merge;
 use serde_json::json;
 use std::process::exit;
 use std::str::FromStr;
+use std::time::{Duration, Instant};
 
 type AppId = String;
 type DeviceId = String;
@@ -34,19 +45,115 @@ fn main() -> Result<()> {
         .init()
         .unwrap();
 
+    let mut headers = matches
+        .values_of(Parameters::header)
+        .into_iter()
+        .flatten()
+        .map(http::parse_header)
+        .collect::<Result<reqwest::header::HeaderMap>>()?;
+    if matches.is_present(Other_flags::trace) {
+        let (trace_id, traceparent) = http::traceparent();
+        eprintln!("Trace id : {}", trace_id);
+        headers.insert("traceparent", traceparent);
+    }
+
+    http::init(http::Settings {
+        config_path: config_path.map(|p| p.to_string()),
+        dump_http: matches
+            .value_of(Parameters::dump_http)
+            .map(|p| p.to_string()),
+        headers,
+        cache: matches.is_present(Other_flags::cache),
+        offline: matches.is_present(Other_flags::offline),
+        user_agent: matches
+            .value_of(Parameters::user_agent)
+            .map(|u| u.to_string()),
+        timeout: matches
+            .value_of(Parameters::timeout)
+            .map(util::parse_duration)
+            .transpose()?,
+        connect_timeout: matches
+            .value_of(Parameters::connect_timeout)
+            .map(util::parse_duration)
+            .transpose()?,
+        curl: matches.is_present(Other_flags::curl),
+        audit_file: matches
+            .value_of(Parameters::audit_file)
+            .map(|p| p.to_string()),
+        // like --timeout, 0 means no limit.
+        deadline: matches
+            .value_of(Parameters::max_time)
+            .map(util::parse_duration)
+            .transpose()?
+            .filter(|d| *d != Duration::from_secs(0))
+            .map(|d| {
+                Instant::now()
+                    .checked_add(d)
+                    .ok_or_else(|| anyhow!("The --max-time is too long."))
+            })
+            .transpose()?,
+    });
+    util::set_env_subst(!matches.is_present(Other_flags::no_env_subst));
+    util::set_quiet(matches.is_present(Other_flags::quiet));
+    util::set_strict_json(matches.is_present(Other_flags::strict_json));
+    config::set_local_config(!matches.is_present(Other_flags::no_local_config));
+
+    let no_config = matches.is_present(Other_flags::no_config);
+    let token_file = matches
+        .value_of(Parameters::token_file)
+        .map(util::read_token_file)
+        .transpose()?;
+
     // load the config file
-    let config_result: Result<Config> =
-        Config::from(config_path).context("Error loading config file");
+    let config_result: Result<Config> = if no_config {
+        config_from_flags(&matches, token_file.as_deref())
+    } else {
+        if reads_token(command) {
+            config::check_permissions(config_path, matches.is_present(Other_flags::strict))?;
+        }
+        Config::from(config_path).context("Error loading config file")
+    };
+
+    if no_config
+        && (command == Other_commands::login.as_ref()
+            || command == Other_commands::context.as_ref())
+    {
+        return Err(anyhow!(
+            "{} needs a configuration file, remove --no-config.",
+            command
+        ));
+    }
 
     if command == Other_commands::login.as_ref() {
         let url = util::url_validation(submatches.unwrap().value_of(Parameters::url).unwrap())?;
         let refresh_token_val = submatches.unwrap().value_of(Other_commands::token);
 
         let mut config = config_result.unwrap_or_else(|_| Config::empty());
+        let timeout = submatches
+            .unwrap()
+            .value_of(Parameters::login_timeout)
+            .map(util::parse_duration)
+            .transpose()?
+            .unwrap_or(openid::DEFAULT_LOGIN_TIMEOUT);
+
         let context = openid::login(
             url.clone(),
             refresh_token_val,
             context_arg.unwrap_or("default".to_string() as ContextId),
+            timeout,
+            submatches
+                .unwrap()
+                .values_of(Parameters::scope)
+                .map(|s| s.map(|s| s.to_string()).collect())
+                .unwrap_or_default(),
+            submatches
+                .unwrap()
+                .value_of(Parameters::audience)
+                .map(|a| a.to_string()),
+            submatches
+                .unwrap()
+                .value_of(Parameters::client_id)
+                .map(|c| c.to_string()),
         )?;
 
         println!("\nSuccessfully authenticated to drogue cloud : {}", url);
@@ -62,6 +169,63 @@ fn main() -> Result<()> {
     } else if command == Other_commands::version.as_ref() {
         util::print_version(&config_result);
         exit(0);
+    } else if command == Other_commands::status.as_ref() {
+        let config_file = if no_config {
+            None
+        } else {
+            Some(config::eval_config_path(config_path))
+        };
+        let healthy = status::status(config_file, config_result, &context_arg);
+        exit(if healthy { 0 } else { 1 });
+    } else if command == Other_commands::doctor.as_ref() {
+        let config_file = if no_config {
+            None
+        } else {
+            Some(config::eval_config_path(config_path))
+        };
+        let healthy = status::doctor(config_file, config_result, &context_arg);
+        exit(if healthy { 0 } else { 1 });
+    } else if command == Other_commands::manpage.as_ref() {
+        print!("{}", manpage::generate()?);
+        exit(0);
+    } else if command == Other_commands::completion.as_ref() {
+        let submatches = submatches.unwrap();
+        let shell = completion::shell(submatches.value_of(Other_commands::completion))?;
+        if submatches.is_present(Other_flags::install) {
+            completion::install(shell)?;
+        } else {
+            completion::print(shell);
+        }
+        exit(0);
+    } else if command == Other_commands::schema.as_ref() {
+        let schema = match Resources::from_str(
+            submatches
+                .unwrap()
+                .value_of(Other_commands::schema)
+                .unwrap(),
+        )? {
+            Resources::app | Resources::apps => schema::app(),
+            Resources::device | Resources::devices => schema::device(),
+        };
+        println!("{}", serde_json::to_string_pretty(&schema)?);
+        exit(0);
+    } else if command == Other_commands::complete_ids.as_ref() {
+        let submatches = submatches.unwrap();
+        completion::print_ids(
+            &config_result,
+            &context_arg,
+            Resources::from_str(submatches.value_of(Other_commands::complete_ids).unwrap())?,
+            submatches.value_of(Resources::app),
+        );
+        exit(0);
+    } else if command == Other_commands::explain.as_ref() {
+        schema::explain(
+            submatches
+                .unwrap()
+                .value_of(Other_commands::explain)
+                .unwrap(),
+        )?;
+        exit(0);
     }
 
     let mut config: Config = config_result?;
@@ -86,12 +250,28 @@ fn main() -> Result<()> {
             Context_subcommands::show => {
                 println!("{}", config);
             }
+            Context_subcommands::current_context => {
+                println!("{}", config.get_context(&None)?.name);
+            }
+            // older files are migrated when loaded
+            Context_subcommands::migrate => {
+                println!(
+                    "The configuration file is up to date, version {}.",
+                    config.version
+                );
+            }
             Context_subcommands::set_active => {
                 config.set_active_context(ctx_id.unwrap())?;
                 config.write(config_path)?;
             }
             Context_subcommands::delete => {
                 let id = ctx_id.unwrap();
+                if config.is_last_context(&id) && !c.unwrap().is_present(Other_flags::force) {
+                    return Err(anyhow!(
+                        "Context {} is the only context in the config file. Use --force to delete it.",
+                        id
+                    ));
+                }
                 config.delete_context(&id)?;
                 config.write(config_path)?;
             }
@@ -108,6 +288,37 @@ fn main() -> Result<()> {
                 config.rename_context(ctx_id.unwrap(), new_ctx)?;
                 config.write(config_path)?;
             }
+            Context_subcommands::set => {
+                let c = c.unwrap();
+                let key = c.value_of("key").unwrap();
+                let value = c.value_of("value").unwrap();
+
+                config.get_context_mut(&context_arg)?.set(
+                    key,
+                    value,
+                    c.is_present(Other_flags::force),
+                )?;
+                config.write(config_path)?;
+            }
+            Context_subcommands::add_template => {
+                let c = c.unwrap();
+                let name = c.value_of(Parameters::template).unwrap();
+                // stored as written : the variables, which may hold secrets, are substituted on use.
+                let spec = util::get_raw_data_from_file(c.value_of(Parameters::filename).unwrap())?;
+                if !spec.is_object() {
+                    return Err(anyhow!("The template must be a JSON or YAML object."));
+                }
+                config.templates.insert(name.to_string(), spec);
+                config.write(config_path)?;
+                println!("Template {} saved.", name);
+            }
+            Context_subcommands::unset => {
+                let c = c.unwrap();
+                config
+                    .get_context_mut(&context_arg)?
+                    .unset(c.value_of("key").unwrap(), c.is_present(Other_flags::force))?;
+                config.write(config_path)?;
+            }
             Context_subcommands::set_default_algo => {
                 let algo = c
                     .unwrap()
@@ -124,7 +335,15 @@ fn main() -> Result<()> {
     }
 
     // The following commands needs a context and a valid token
-    if openid::verify_token_validity(config.get_context_mut(&context_arg)?)? {
+    if let Some(token) = &token_file {
+        openid::use_token(config.get_context_mut(&context_arg)?, token);
+    }
+    let offline = matches.is_present(Other_flags::offline);
+    if (!no_config || token_file.is_some())
+        && !offline
+        && !matches.is_present(Other_flags::no_verify_token)
+        && openid::verify_token_validity(config.get_context_mut(&context_arg)?)?
+    {
         config.write(config_path)?;
     }
     let context = config.get_context(&context_arg)?;
@@ -133,7 +352,10 @@ fn main() -> Result<()> {
         let (_, submatches) = matches.subcommand();
         let (_, endpoints_matches) = submatches.map(|s| s.subcommand()).unwrap_or(("", None));
         if submatches.unwrap().is_present(Other_commands::token) {
-            openid::print_token(&context);
+            openid::print_token(
+                &context,
+                submatches.unwrap().is_present(Other_flags::clipboard),
+            );
         } else if let Some(endpoints_matches) = endpoints_matches {
             let service = match endpoints_matches.value_of(Other_commands::endpoints) {
                 Some("*") => None,
@@ -147,6 +369,53 @@ fn main() -> Result<()> {
         exit(0)
     }
 
+    if command == Other_commands::ping.as_ref() {
+        let count = submatches
+            .unwrap()
+            .value_of(Parameters::count)
+            .map(|c| c.parse::<usize>())
+            .transpose()?
+            .unwrap_or(5);
+        exit(if status::ping(&context, count) { 0 } else { 1 });
+    }
+
+    if command == Other_commands::tree.as_ref() {
+        let depth = submatches
+            .unwrap()
+            .value_of(Parameters::depth)
+            .map(|d| d.parse::<usize>())
+            .transpose()?
+            .unwrap_or(2);
+        let width = submatches
+            .unwrap()
+            .value_of(Parameters::width)
+            .map(|w| w.parse::<usize>())
+            .transpose()?;
+
+        tree::print(&context, depth, width)?;
+        exit(0)
+    }
+
+    if command == Other_commands::export.as_ref() {
+        let (_, export) = submatches.unwrap().subcommand();
+        let export = export.unwrap();
+        let app_id = arguments::get_app_id(&export, &context)?;
+
+        let devices = match export.value_of(Parameters::id) {
+            Some(id) => vec![devices::get_device(&context, &app_id, id)?],
+            None => devices::get_list(&context, &app_id, None, false)?,
+        };
+        devices::export(
+            &app_id,
+            devices,
+            export.value_of(Parameters::out_dir).unwrap(),
+            export
+                .value_of(Parameters::name_template)
+                .unwrap_or("{name}.json"),
+        )?;
+        exit(0)
+    }
+
     if command == Other_commands::stream.as_ref() {
         let (_, matches) = matches.subcommand();
         let app_id = arguments::get_app_id(&matches.unwrap(), &context)?;
@@ -240,8 +509,63 @@ fn main() -> Result<()> {
     log::warn!("Using context: {}", context.name);
     let verb = Verbs::from_str(command);
     let cmd = submatches.unwrap();
+    // the app used by a successful device operation, to be remembered in the context
+    let mut last_app: Option<AppId> = None;
 
     match verb? {
+        // all the resources of a file, each with its kind.
+        Verbs::create if cmd.subcommand_name().is_none() => {
+            let file = cmd.value_of(Parameters::filename).ok_or_else(|| {
+                anyhow!("Specify a resource to create, or a file with --filename")
+            })?;
+            let documents = util::get_documents_from_file(file)?;
+
+            let mut created = 0;
+            for mut document in documents.iter().cloned() {
+                if http::deadline_exceeded() {
+                    eprintln!("The --max-time is exceeded, stopping.");
+                    break;
+                }
+                let name = util::resource_name(&document);
+                let kind = document
+                    .as_object_mut()
+                    .and_then(|d| d.remove("kind"))
+                    .and_then(|k| k.as_str().map(|k| k.to_lowercase()));
+
+                let result = match kind.as_deref().map(Resources::from_str) {
+                    Some(Ok(Resources::app)) => apps::post(&context, &document),
+                    Some(Ok(Resources::device)) => {
+                        match document["metadata"]["application"].as_str() {
+                            Some(app) => Ok(app.to_string()),
+                            None => arguments::get_app_id(&cmd, &context),
+                        }
+                        .and_then(|app| {
+                            document["metadata"]["application"] = json!(app);
+                            devices::post(&context, &app, &document)
+                        })
+                    }
+                    _ => Err(anyhow!("expected a kind of app or device")),
+                };
+
+                let kind = kind.unwrap_or_default();
+                match result {
+                    Ok(res) if res.status().is_success() => {
+                        println!("{} {} created.", kind, name);
+                        created += 1;
+                    }
+                    Ok(res) => {
+                        eprintln!("{} {} could not be created : {}", kind, name, res.status())
+                    }
+                    Err(e) => eprintln!("{} {} could not be created : {}", kind, name, e),
+                }
+            }
+            println!("{}/{} resources created", created, documents.len());
+
+            let code = util::bulk_exit_code(created, documents.len());
+            if code != 0 {
+                exit(code);
+            }
+        }
         Verbs::create => {
             let (res, command) = cmd.subcommand();
             let mut data = util::json_parse(command.unwrap().value_of(Parameters::spec))?;
@@ -253,12 +577,20 @@ fn main() -> Result<()> {
 
             let resource = Resources::from_str(res);
             let file = command.unwrap().value_of(Parameters::filename);
+            let id_only =
+                command.unwrap().value_of(Parameters::output) == Some(Outputs::id.as_ref());
 
             match resource? {
-                Resources::app => apps::create(&context, id, data, file),
+                Resources::app => apps::create(&context, id, data, file, id_only),
                 Resources::device => {
                     let app_id = arguments::get_app_id(&command.unwrap(), &context)?;
 
+                    if let Some(name) = command.unwrap().value_of(Parameters::template) {
+                        let mut spec = util::env_subst_value(config.template(name)?)?;
+                        spec.merge(data);
+                        data = spec;
+                    }
+
                     // add an alias with the correct subject dn.
                     if command.unwrap().is_present(&Other_flags::cert) {
                         let alias = format!("CN={}, O=Drogue IoT, OU={}", id, app_id);
@@ -266,12 +598,37 @@ fn main() -> Result<()> {
                         data.merge_in("/alias", alias_spec)
                     }
 
-                    devices::create(&context, id, data, app_id, file)
+                    let password = if command.unwrap().is_present(Other_flags::password_stdin) {
+                        Some(util::read_stdin_line()?)
+                    } else {
+                        command
+                            .unwrap()
+                            .value_of(Parameters::password)
+                            .map(|p| p.to_string())
+                    };
+                    let credentials =
+                        devices::credentials(password, command.unwrap().value_of(Parameters::psk))?;
+                    if !credentials.is_empty() {
+                        data.merge_in("/credentials/credentials", json!(credentials))
+                    }
+
+                    last_app = Some(app_id.clone());
+                    devices::create(&context, id, data, app_id, file, id_only)
                 }
                 // ignore apps and devices keywords
                 _ => Err(anyhow!("Cannot create multiple resources")),
             }?;
         }
+        // also reached with `remove member`, the alias of delete.
+        Verbs::delete if cmd.subcommand_name() == Some(Set_targets::member.as_ref()) => {
+            let command = cmd.subcommand_matches(Set_targets::member).unwrap();
+            apps::remove_member(
+                &context,
+                command.value_of(Parameters::id).unwrap(),
+                command.value_of(Set_args::user).unwrap(),
+                command.is_present(Other_flags::yes),
+            )?;
+        }
         Verbs::delete => {
             let (res, command) = cmd.subcommand();
             let id = command
@@ -281,12 +638,14 @@ fn main() -> Result<()> {
                 .to_string();
             let resource = Resources::from_str(res);
             let ignore_missing = command.unwrap().is_present(Other_flags::ignore_missing);
+            let force = command.unwrap().is_present(Other_flags::force);
 
             match resource? {
-                Resources::app => apps::delete(&context, id, ignore_missing),
+                Resources::app => apps::delete(&context, id, ignore_missing, force),
                 Resources::device => {
                     let app_id = arguments::get_app_id(&command.unwrap(), &context)?;
-                    devices::delete(&context, app_id, id, ignore_missing)
+                    last_app = Some(app_id.clone());
+                    devices::delete(&context, app_id, id, ignore_missing, force)
                 }
                 // ignore apps and devices keywords
                 _ => Err(anyhow!("Cannot delete multiple resources")),
@@ -300,13 +659,17 @@ fn main() -> Result<()> {
                 .unwrap()
                 .to_string();
             let file = command.unwrap().value_of(Parameters::filename);
+            let version = command.unwrap().value_of(Parameters::resource_version);
+            let path = command.unwrap().value_of(Parameters::path);
+            let yes = command.unwrap().is_present(Other_flags::yes);
             let resource = Resources::from_str(res);
 
             match resource? {
-                Resources::app => apps::edit(&context, id, file),
+                Resources::app => apps::edit(&context, id, file, version, path, yes),
                 Resources::device => {
                     let app_id = arguments::get_app_id(&command.unwrap(), &context)?;
-                    devices::edit(&context, app_id, id, file)
+                    last_app = Some(app_id.clone());
+                    devices::edit(&context, app_id, id, file, version, path, yes)
                 }
                 // ignore apps and devices keywords
                 _ => Err(anyhow!("Cannot edit multiple resources")),
@@ -327,56 +690,391 @@ fn main() -> Result<()> {
                 .values_of(Parameters::labels)
                 .map(|v| v.collect::<Vec<&str>>().join(","));
 
+            let output = arguments::get_output(command.unwrap())?;
+            let watch_resource = command.unwrap().is_present(Other_flags::watch);
+            let status_only = command.unwrap().is_present(Other_flags::watch_only_status);
+            let interval = command
+                .unwrap()
+                .value_of(Parameters::interval)
+                .map(util::parse_duration)
+                .transpose()?
+                .unwrap_or(watch::DEFAULT_POLL_INTERVAL);
+            let history = command.unwrap().is_present(Other_flags::history);
+            let include_deleted = command.unwrap().is_present(Other_flags::include_deleted);
+            let role = command
+                .unwrap()
+                .value_of(Parameters::role)
+                .map(Roles::from_str)
+                .transpose()?;
+            let revision = command
+                .unwrap()
+                .value_of(Parameters::revision)
+                .map(|r| r.parse::<u64>())
+                .transpose()?;
+
             match resource {
                 Resources::app | Resources::apps => {
                     match id {
-                        Some(id) => apps::read(&context, id as AppId),
-                        None => apps::list(&context, labels),
+                        Some(id) if watch_resource => {
+                            apps::watch(&context, id as AppId, interval, status_only)
+                        }
+                        Some(id) if history => apps::history(&context, id as AppId),
+                        Some(id) => match revision {
+                            Some(r) => apps::read_revision(&context, id as AppId, r, &output),
+                            None => apps::read(&context, id as AppId, include_deleted, &output),
+                        },
+                        None => apps::list(&context, labels, include_deleted, role, &output),
                     }?;
                 }
+                Resources::devices if command.unwrap().is_present(Parameters::app_selector) => {
+                    let selector = command
+                        .unwrap()
+                        .values_of(Parameters::app_selector)
+                        .map(|v| v.collect::<Vec<&str>>().join(","));
+                    let apps = apps::get_list(&context, selector, false)?
+                        .iter()
+                        .map(util::resource_name)
+                        .collect();
+                    devices::list_across(
+                        &context,
+                        apps,
+                        labels,
+                        include_deleted,
+                        command.unwrap().is_present(Other_flags::only_connected),
+                        &output,
+                    )?;
+                }
                 Resources::device | Resources::devices => {
                     let app_id = arguments::get_app_id(&command.unwrap(), &context)?;
+                    last_app = Some(app_id.clone());
+
+                    // on a terminal, `get device` without an id asks which one to read.
+                    let id = match id {
+                        None if matches!(resource, Resources::device)
+                            && output.is_default()
+                            && util::is_interactive() =>
+                        {
+                            match util::pick(
+                                devices::names(&context, &app_id, labels.clone())?,
+                                "device",
+                            )? {
+                                Some(name) => Some(name),
+                                None => return Ok(()),
+                            }
+                        }
+                        id => id,
+                    };
                     match id {
-                        Some(id) => devices::read(&context, app_id, id as DeviceId),
-                        None => devices::list(&context, app_id, labels),
+                        Some(id) if watch_resource => {
+                            devices::watch(&context, app_id, id as DeviceId, interval, status_only)
+                        }
+                        Some(id) if history => devices::history(&context, app_id, id as DeviceId),
+                        Some(id) if command.unwrap().is_present(Other_flags::connected) => {
+                            devices::read_connection(&context, app_id, id as DeviceId, &output)
+                        }
+                        Some(id) => match revision {
+                            Some(r) => {
+                                devices::read_revision(&context, app_id, id as DeviceId, r, &output)
+                            }
+                            None => devices::read(
+                                &context,
+                                app_id,
+                                id as DeviceId,
+                                command.unwrap().is_present(Other_flags::resolve_aliases),
+                                command.unwrap().is_present(Other_flags::decode_psk),
+                                include_deleted,
+                                &output,
+                            ),
+                        },
+                        None => devices::list(
+                            &context,
+                            app_id,
+                            labels,
+                            include_deleted,
+                            command.unwrap().is_present(Other_flags::only_connected),
+                            &output,
+                        ),
                     }?;
                 }
             }
         }
+        // members are set on apps, unlike the other targets.
+        Verbs::set if cmd.subcommand_name() == Some(Set_targets::member.as_ref()) => {
+            let command = cmd.subcommand_matches(Set_targets::member).unwrap();
+            apps::set_member(
+                &context,
+                command.value_of(Parameters::id).unwrap(),
+                command.value_of(Set_args::user).unwrap(),
+                Member_roles::from_str(command.value_of(Set_args::role).unwrap())?,
+            )?;
+        }
         Verbs::set => {
             let (target, command) = cmd.subcommand();
             let args: Vec<&str> = command.unwrap().values_of(Verbs::set).unwrap().collect();
 
-            // clap already makes sure vals contains two values
-            let (device, value) = (args[0].to_string(), args[1].to_string());
+            // clap already makes sure vals contains two values, except for a generated password
+            let device = args[0].to_string();
+            let value = args.get(1).map(|v| v.to_string());
             let app_id = arguments::get_app_id(&command.unwrap(), &context)?;
+            last_app = Some(app_id.clone());
 
             match Set_targets::from_str(target)? {
                 Set_targets::gateway => {
-                    devices::set_gateway(&context, app_id, device as DeviceId, value)?;
+                    devices::set_gateway(&context, app_id, device as DeviceId, value.unwrap())?;
                 }
                 Set_targets::password => {
                     let username = command.unwrap().value_of(Set_args::username);
-                    devices::set_password(&context, app_id, device as DeviceId, value, username)?;
+                    let generate = command.unwrap().is_present(Set_args::generate);
+
+                    let password = match (value, generate) {
+                        (Some(password), false) => password,
+                        (None, true) => devices::generate_password(
+                            command
+                                .unwrap()
+                                .value_of(Set_args::length)
+                                .map(|l| l.parse::<usize>())
+                                .transpose()?
+                                .unwrap_or(32),
+                        ),
+                        (Some(_), true) => {
+                            return Err(anyhow!("A password cannot be provided with --generate."))
+                        }
+                        (None, false) => {
+                            return Err(anyhow!(
+                                "Missing password. Use --generate to generate one."
+                            ))
+                        }
+                    };
+
+                    devices::set_password(
+                        &context,
+                        app_id,
+                        device as DeviceId,
+                        password.clone(),
+                        username,
+                    )?;
+                    if generate {
+                        println!("{}", password);
+                        eprintln!(
+                            "This password cannot be retrieved later, make sure to store it."
+                        );
+                    }
                 }
                 Set_targets::alias => {
-                    devices::add_alias(&context, app_id, device as DeviceId, value)?;
+                    devices::add_alias(&context, app_id, device as DeviceId, value.unwrap())?;
+                }
+                // handled above
+                Set_targets::member => {}
+            }
+        }
+        Verbs::transfer => {
+            // apps are the only resources with an owner
+            let (_, command) = cmd.subcommand();
+            let command = command.unwrap();
+            apps::transfer(
+                &context,
+                command.value_of(Parameters::id).unwrap(),
+                command.value_of(Set_args::to).unwrap(),
+            )?;
+        }
+        Verbs::accept => {
+            let (_, command) = cmd.subcommand();
+            apps::accept(&context, command.unwrap().value_of(Parameters::id).unwrap())?;
+        }
+        verb @ Verbs::label | verb @ Verbs::annotate => {
+            let (res, command) = cmd.subcommand();
+            let command = command.unwrap();
+            let app_id = arguments::get_app_id(&command, &context)?;
+            let mut changes = util::parse_metadata_changes(
+                &command
+                    .values_of(Parameters::changes)
+                    .unwrap()
+                    .collect::<Vec<&str>>(),
+            )?;
+            let field = match verb {
+                Verbs::annotate => {
+                    util::read_value_files(&mut changes)?;
+                    "annotations"
+                }
+                _ => "labels",
+            };
+
+            match Resources::from_str(res)? {
+                Resources::devices => {
+                    let selector = command
+                        .values_of(Parameters::labels)
+                        .map(|v| v.collect::<Vec<&str>>().join(","));
+                    let devices = devices::get_list(&context, &app_id, selector, false)?;
+
+                    let mut updated = 0;
+                    for device in &devices {
+                        if http::deadline_exceeded() {
+                            eprintln!("The --max-time is exceeded, stopping.");
+                            break;
+                        }
+                        let device_id = util::resource_name(device);
+                        match devices::update_metadata(
+                            &context, &app_id, &device_id, field, &changes,
+                        ) {
+                            Ok(()) => updated += 1,
+                            Err(e) => eprintln!("{}", e),
+                        }
+                    }
+                    println!("{}/{} devices updated", updated, devices.len());
+
+                    let code = util::bulk_exit_code(updated, devices.len());
+                    if code != 0 {
+                        exit(code);
+                    }
+                }
+                _ => {
+                    let device_id = command.value_of(Parameters::id).unwrap().to_string();
+                    devices::update_metadata(&context, &app_id, &device_id, field, &changes)?;
                 }
             }
+            last_app = Some(app_id);
+        }
+        Verbs::rollback => {
+            // device is the only resource that can be rolled back
+            let (_, command) = cmd.subcommand();
+            let command = command.unwrap();
+            let id = command.value_of(Parameters::id).unwrap().to_string();
+            let app_id = arguments::get_app_id(&command, &context)?;
+            let revision = command
+                .value_of(Parameters::to_revision)
+                .map(|r| r.parse::<u64>())
+                .transpose()?;
+
+            devices::rollback(
+                &context,
+                app_id.clone(),
+                id as DeviceId,
+                revision,
+                command.value_of(Parameters::from_file),
+                command.is_present(Other_flags::yes),
+            )?;
+            last_app = Some(app_id);
         }
         Verbs::cmd => {
-            let args: Vec<&str> = cmd.values_of(Verbs::cmd).unwrap().collect();
+            let command = cmd.value_of(Verbs::cmd).unwrap();
             let app_id = arguments::get_app_id(&cmd, &context)?;
-            let (command, device) = (args[0], args[1]);
 
             let body = match cmd.value_of(Parameters::filename) {
                 Some(f) => util::get_data_from_file(f)?,
                 None => util::json_parse(cmd.value_of(Parameters::payload))?,
             };
 
-            command::send_command(&context, app_id.as_str(), device, command, body)?;
+            if cmd.is_present(Other_flags::all_devices) {
+                let devices: Vec<DeviceId> = devices::get_list(&context, &app_id, None, false)?
+                    .iter()
+                    .filter_map(|d| d["metadata"]["name"].as_str().map(|n| n.to_string()))
+                    .collect();
+
+                if !cmd.is_present(Other_flags::yes) {
+                    return Err(anyhow!(
+                        "This would send the command {} to {} devices of app {}. Use --yes to confirm.",
+                        command,
+                        devices.len(),
+                        app_id
+                    ));
+                }
+
+                let concurrency = cmd
+                    .value_of(Parameters::concurrency)
+                    .map(|c| c.parse::<usize>())
+                    .transpose()?
+                    .unwrap_or(1);
+
+                let outcomes = command::broadcast_command(
+                    &context,
+                    app_id.as_str(),
+                    devices,
+                    command,
+                    body,
+                    concurrency,
+                );
+                command::print_summary(
+                    &outcomes,
+                    command,
+                    cmd.value_of(Parameters::output) == Some(Outputs::json.as_ref()),
+                )?;
+
+                let accepted = outcomes.iter().filter(|o| o.succeeded()).count();
+                let code = util::bulk_exit_code(accepted, outcomes.len());
+                if code != 0 {
+                    exit(code);
+                }
+            } else {
+                let device = cmd.value_of(Resources::device).unwrap();
+                match cmd.value_of(Parameters::count) {
+                    Some(count) => {
+                        let interval = cmd
+                            .value_of(Parameters::interval)
+                            .map(util::parse_duration)
+                            .transpose()?
+                            .unwrap_or_default();
+
+                        command::send_command_repeat(
+                            &context,
+                            app_id.as_str(),
+                            device,
+                            command,
+                            body,
+                            count.parse()?,
+                            interval,
+                        )?;
+                    }
+                    None => command::send_command(
+                        &context,
+                        app_id.as_str(),
+                        device,
+                        command,
+                        body,
+                        cmd.value_of(Parameters::output) == Some(Outputs::json.as_ref()),
+                    )?,
+                }
+            }
+            last_app = Some(app_id);
+        }
+    }
+
+    if let Some(app) = last_app {
+        if matches.is_present(Other_flags::remember_app) && !no_config {
+            // reloaded : the token may have been refreshed and saved during the command,
+            // and the token from --token-file must not end up in the config file.
+            let mut config = Config::from(config_path)?;
+            config.get_context_mut(&context_arg)?.last_app = Some(app);
+            config.write(config_path)?;
         }
     }
 
     Ok(())
 }
+
+fn config_from_flags(matches: &clap::ArgMatches, token_file: Option<&str>) -> Result<Config> {
+    let url = matches
+        .value_of(Parameters::registry_url)
+        .ok_or_else(|| anyhow!("--no-config requires --registry-url or DRG_REGISTRY_URL."))?;
+    let token = token_file
+        .or_else(|| matches.value_of(Parameters::access_token))
+        .ok_or_else(|| {
+            anyhow!("--no-config requires --access-token, DRG_TOKEN or --token-file.")
+        })?;
+
+    Ok(Config::from_token(util::url_validation(url)?, token))
+}
+
+// The permissions of the config file only matter to the commands using its tokens.
+// Doctor reports them instead, and completions must stay silent.
+fn reads_token(command: &str) -> bool {
+    ![
+        Other_commands::doctor.as_ref(),
+        Other_commands::version.as_ref(),
+        Other_commands::manpage.as_ref(),
+        Other_commands::completion.as_ref(),
+        Other_commands::complete_ids.as_ref(),
+        Other_commands::schema.as_ref(),
+        Other_commands::explain.as_ref(),
+    ]
+    .contains(&command)
+}
@@ -0,0 +1,71 @@
+use crate::{arguments, util};
+use anyhow::{anyhow, Result};
+use clap::ErrorKind;
+use std::iter;
+
+// Generate a man page in roff from the help of drg and of each of its subcommands.
+pub fn generate() -> Result<String> {
+    let mut page = format!(".TH DRG 1 \"\" \"drg {}\"\n", util::VERSION);
+    page.push_str(".SH NAME\ndrg \\- Drogue Command Line Tool\n");
+
+    write_section(&mut page, &mut Vec::new())?;
+    Ok(page)
+}
+
+fn write_section(page: &mut String, path: &mut Vec<String>) -> Result<()> {
+    let help = help_text(path)?;
+    let title = if path.is_empty() {
+        "DESCRIPTION".to_string()
+    } else {
+        format!("DRG {}", path.join(" ").to_uppercase())
+    };
+
+    page.push_str(&format!(".SH \"{}\"\n.nf\n{}\n.fi\n", title, escape(&help)));
+
+    for subcommand in subcommands(&help) {
+        path.push(subcommand);
+        write_section(page, path)?;
+        path.pop();
+    }
+    Ok(())
+}
+
+// clap hands the help text back as an error when --help is passed.
+fn help_text(path: &[String]) -> Result<String> {
+    let args = iter::once("drg")
+        .chain(path.iter().map(String::as_str))
+        .chain(iter::once("--help"));
+
+    match arguments::app().get_matches_from_safe(args) {
+        Err(e) if e.kind == ErrorKind::HelpDisplayed => Ok(e.message),
+        _ => Err(anyhow!("Could not read the help of drg {}", path.join(" "))),
+    }
+}
+
+// Subcommand names are the first word of the lines of the SUBCOMMANDS section,
+// wrapped descriptions are indented further.
+fn subcommands(help: &str) -> Vec<String> {
+    help.lines()
+        .skip_while(|l| !l.starts_with("SUBCOMMANDS:"))
+        .skip(1)
+        .take_while(|l| !l.trim().is_empty())
+        .filter(|l| l.starts_with("    ") && !l.starts_with("     "))
+        .filter_map(|l| l.split_whitespace().next())
+        .filter(|name| *name != "help")
+        .map(String::from)
+        .collect()
+}
+
+fn escape(text: &str) -> String {
+    text.lines()
+        .map(|l| {
+            let l = l.replace('\\', "\\e");
+            if l.starts_with('.') || l.starts_with('\'') {
+                format!("\\&{}", l)
+            } else {
+                l
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
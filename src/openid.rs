@@ -1,8 +1,10 @@
-use oauth2::basic::{BasicClient, BasicTokenResponse};
+use oauth2::basic::{
+    BasicClient, BasicErrorResponse, BasicErrorResponseType, BasicTokenResponse, BasicTokenType,
+};
 use oauth2::reqwest::http_client;
 use oauth2::{
-    AuthUrl, AuthorizationCode, ClientId, CsrfToken, PkceCodeChallenge, RedirectUrl, Scope,
-    TokenResponse, TokenUrl,
+    AccessToken, AuthUrl, AuthorizationCode, ClientId, CsrfToken, EmptyExtraTokenFields,
+    PkceCodeChallenge, RedirectUrl, RequestTokenError, Scope, TokenResponse, TokenUrl,
 };
 
 use anyhow::Error;
@@ -14,16 +16,23 @@ use qstring::QString;
 use reqwest::Url;
 
 use crate::config::{self, Context};
-use crate::util;
-use chrono::{DateTime, Duration, Utc};
+use crate::{http, util};
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
 use std::net::{Ipv4Addr, SocketAddr};
+use std::time::Duration as StdDuration;
 
-const CLIENT_ID: &str = "drogue";
+pub const CLIENT_ID: &str = "drogue";
+const DEFAULT_SCOPES: &[&str] = &["offline_access"];
+pub const DEFAULT_LOGIN_TIMEOUT: StdDuration = StdDuration::from_secs(300);
 
 pub fn login(
     api_endpoint: Url,
     refresh_token_val: Option<&str>,
     context_name: config::ContextId,
+    timeout: StdDuration,
+    scopes: Vec<String>,
+    audience: Option<String>,
+    client_id: Option<String>,
 ) -> Result<Context> {
     log::info!("Starting authentication process with {}", api_endpoint);
 
@@ -35,8 +44,18 @@ pub fn login(
             auth_url.clone(),
             token_url.clone(),
             &oauth2::RefreshToken::new(refresh_token_val.to_string()),
+            &scopes,
+            audience.as_deref(),
+            client_id.as_deref().unwrap_or(CLIENT_ID),
+        )?,
+        None => get_token(
+            auth_url.clone(),
+            token_url.clone(),
+            timeout,
+            &scopes,
+            audience.as_deref(),
+            client_id.as_deref().unwrap_or(CLIENT_ID),
         )?,
-        None => get_token(auth_url.clone(), token_url.clone())?,
     };
 
     let token_exp_date = calculate_token_expiration_date(&token)?;
@@ -48,18 +67,30 @@ pub fn login(
         name: context_name,
         drogue_cloud_url: api_endpoint,
         default_app: None,
+        last_app: None,
         default_algo: None,
         token,
         token_url,
         auth_url,
         registry_url,
         token_exp_date,
+        scopes,
+        audience,
+        client_id,
+        extra: Default::default(),
     };
 
     Ok(config)
 }
 
-fn get_token(auth_url: Url, token_url: Url) -> Result<BasicTokenResponse> {
+fn get_token(
+    auth_url: Url,
+    token_url: Url,
+    timeout: StdDuration,
+    scopes: &[String],
+    audience: Option<&str>,
+    client_id: &str,
+) -> Result<BasicTokenResponse> {
     log::debug!("Using auth url : {}", auth_url);
 
     //start a local server
@@ -68,7 +99,7 @@ fn get_token(auth_url: Url, token_url: Url) -> Result<BasicTokenResponse> {
     let port = server.server_addr().port();
 
     let client = BasicClient::new(
-        ClientId::new(CLIENT_ID.to_string()),
+        ClientId::new(client_id.to_string()),
         None,
         AuthUrl::new(auth_url.to_string())?,
         Some(TokenUrl::new(token_url.to_string())?),
@@ -80,11 +111,16 @@ fn get_token(auth_url: Url, token_url: Url) -> Result<BasicTokenResponse> {
     let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
 
     // Generate the full authorization URL.
-    let (final_auth_url, csrf_token) = client
+    let mut auth_request = client
         .authorize_url(CsrfToken::new_random)
-        .add_scope(Scope::new("offline_access".to_string()))
-        .set_pkce_challenge(pkce_challenge)
-        .url();
+        .set_pkce_challenge(pkce_challenge);
+    for scope in with_default_scopes(scopes) {
+        auth_request = auth_request.add_scope(scope);
+    }
+    if let Some(audience) = audience {
+        auth_request = auth_request.add_extra_param("audience", audience);
+    }
+    let (final_auth_url, csrf_token) = auth_request.url();
 
     // The URL the user should browse to, in order to trigger the authorization process.
     log::info!("Opening browser.");
@@ -96,11 +132,29 @@ fn get_token(auth_url: Url, token_url: Url) -> Result<BasicTokenResponse> {
         );
     }
 
-    // get the request from the localhost webserver
-    let request = server.recv()?;
+    // get the request from the localhost webserver. The server is dropped when giving up, releasing the port.
+    let request = server.recv_timeout(timeout)?.ok_or_else(|| {
+        Error::msg(format!(
+            "Login timed out, the authentication was not completed in the browser within {}s.",
+            timeout.as_secs()
+        ))
+    })?;
 
     // extract code and state from the openID server request
     let querry = QString::from(request.url().trim_start_matches("/?"));
+    if let Some(error) = querry.get("error") {
+        let _ = request.respond(Response::from_string(
+            "Authentication failed! This browser can be closed.",
+        ));
+        return Err(match error {
+            "invalid_client" | "unauthorized_client" => client_id_error(client_id),
+            e => Error::msg(format!(
+                "Authentication failed : {} {}",
+                e,
+                querry.get("error_description").unwrap_or_default()
+            )),
+        });
+    }
     let state = querry.get("state").unwrap();
     let code = querry.get("code").unwrap();
 
@@ -111,11 +165,14 @@ fn get_token(auth_url: Url, token_url: Url) -> Result<BasicTokenResponse> {
     assert_eq!(csrf_token.secret().as_str(), state);
 
     // Now trade it for an access token.
-    let token_result = client
+    let mut token_request = client
         .exchange_code(AuthorizationCode::new(code.to_string()))
         // Set the PKCE code verifier.
-        .set_pkce_verifier(pkce_verifier)
-        .request(http_client);
+        .set_pkce_verifier(pkce_verifier);
+    if let Some(audience) = audience {
+        token_request = token_request.add_extra_param("audience", audience);
+    }
+    let token_result = token_request.request(http_client);
 
     let browser_msg = match token_result {
         Ok(_) => "Authentication success. This browser can be closed.",
@@ -125,6 +182,9 @@ fn get_token(auth_url: Url, token_url: Url) -> Result<BasicTokenResponse> {
 
     // Unwrapping token_result will either produce a Token or a RequestTokenError.
     token_result.map_err(|e| {
+        if is_client_error(&e) {
+            return client_id_error(client_id);
+        }
         Error::msg(format!(
             "error while requesting a token: \n{}",
             e.to_string()
@@ -137,13 +197,18 @@ pub fn verify_token_validity(context: &mut Context) -> Result<bool> {
     // 30 seconds should be enough
     if context.token_exp_date - Utc::now() > Duration::seconds(30) {
         Ok(false)
+    } else if context.token.refresh_token().is_none() {
+        Err(Error::msg(format!(
+            "The access token expires at {} and cannot be refreshed.",
+            context.token_exp_date
+        )))
     } else {
         log::info!("Token is expired or will be soon, refreshing...");
         refresh_token(context)
     }
 }
 
-fn refresh_token(context: &mut Context) -> Result<bool> {
+pub fn refresh_token(context: &mut Context) -> Result<bool> {
     let refresh_token_var = context
         .token
         .refresh_token()
@@ -152,6 +217,9 @@ fn refresh_token(context: &mut Context) -> Result<bool> {
         context.auth_url.clone(),
         context.token_url.clone(),
         &refresh_token_var,
+        &context.scopes,
+        context.audience.as_deref(),
+        context.client_id.as_deref().unwrap_or(CLIENT_ID),
     )?;
 
     context.token_exp_date = calculate_token_expiration_date(&new_token)?;
@@ -167,6 +235,9 @@ fn exchange_token(
     auth_url: Url,
     token_url: Url,
     refresh_token_val: &oauth2::RefreshToken,
+    scopes: &[String],
+    audience: Option<&str>,
+    client_id: &str,
 ) -> Result<BasicTokenResponse> {
     log::debug!("Refreshing token using url : {}", &token_url);
 
@@ -174,20 +245,60 @@ fn exchange_token(
     let token_url = TokenUrl::new(token_url.to_string())?;
 
     let client = BasicClient::new(
-        ClientId::new(CLIENT_ID.to_string()),
+        ClientId::new(client_id.to_string()),
         None,
         auth_url,
         Some(token_url),
     );
 
-    // Exchange the refresh token for access token
-    client
-        .exchange_refresh_token(refresh_token_val)
-        .request(http_client)
-        .map_err(|e| {
-            log::warn!("{:?}", e);
-            Error::msg(format!("While refreshing token : {}", e))
-        })
+    // Exchange the refresh token for access token, keeping the scopes requested at login.
+    let mut request = client.exchange_refresh_token(refresh_token_val);
+    for scope in scopes {
+        request = request.add_scope(Scope::new(scope.clone()));
+    }
+    if let Some(audience) = audience {
+        request = request.add_extra_param("audience", audience);
+    }
+
+    request.request(http_client).map_err(|e| {
+        log::warn!("{:?}", e);
+        if is_client_error(&e) {
+            return client_id_error(client_id);
+        }
+        Error::msg(format!("While refreshing token : {}", e))
+    })
+}
+
+// The server does not know the client, or does not allow it to use this flow.
+fn is_client_error<RE>(e: &RequestTokenError<RE, BasicErrorResponse>) -> bool
+where
+    RE: std::error::Error + 'static,
+{
+    match e {
+        RequestTokenError::ServerResponse(r) => matches!(
+            r.error(),
+            BasicErrorResponseType::InvalidClient | BasicErrorResponseType::UnauthorizedClient
+        ),
+        _ => false,
+    }
+}
+
+fn client_id_error(client_id: &str) -> Error {
+    Error::msg(format!(
+        "The server rejected the OAuth client \"{}\". If this installation uses another client, log in again with --client-id.",
+        client_id
+    ))
+}
+
+fn with_default_scopes(scopes: &[String]) -> Vec<Scope> {
+    if scopes.is_empty() {
+        DEFAULT_SCOPES
+            .iter()
+            .map(|s| Scope::new(s.to_string()))
+            .collect()
+    } else {
+        scopes.iter().map(|s| Scope::new(s.clone())).collect()
+    }
 }
 
 fn calculate_token_expiration_date(token: &BasicTokenResponse) -> Result<DateTime<Utc>> {
@@ -200,8 +311,44 @@ fn calculate_token_expiration_date(token: &BasicTokenResponse) -> Result<DateTim
         .ok_or_else(|| anyhow::Error::msg("Error calculating token expiration date"))
 }
 
-pub fn print_token(context: &Context) {
-    println!("{}", context.token.access_token().secret());
+// Use a token obtained elsewhere, e.g. with --token-file, instead of the one of the context.
+// It can't be refreshed. Its expiration date is read from the token when it's a JWT.
+pub fn use_token(context: &mut Context, token: &str) {
+    context.token = BasicTokenResponse::new(
+        AccessToken::new(token.to_string()),
+        BasicTokenType::Bearer,
+        EmptyExtraTokenFields {},
+    );
+    context.token_exp_date = claims(token)
+        .and_then(|c| c["exp"].as_i64())
+        .map(|exp| DateTime::from_utc(NaiveDateTime::from_timestamp(exp, 0), Utc))
+        // the server is left to reject an opaque token.
+        .unwrap_or(chrono::MAX_DATETIME);
+}
+
+// The claims of a JWT.
+fn claims(token: &str) -> Option<serde_json::Value> {
+    token
+        .split('.')
+        .nth(1)
+        .and_then(|c| base64::decode_config(c, base64::URL_SAFE_NO_PAD).ok())
+        .and_then(|c| serde_json::from_slice::<serde_json::Value>(&c).ok())
+}
+
+// The user id is the subject of the access token, a JWT.
+pub fn user_id(context: &Context) -> Result<String> {
+    let token = http::current_token(context);
+    let claims = claims(token.access_token().secret())
+        .ok_or_else(|| Error::msg("Cannot read the user id, the access token is not a JWT."))?;
+
+    claims["sub"]
+        .as_str()
+        .map(|s| s.to_string())
+        .ok_or_else(|| Error::msg("Cannot read the user id, the access token has no subject."))
+}
+
+pub fn print_token(context: &Context, clipboard: bool) {
+    util::print_or_copy(context.token.access_token().secret(), clipboard);
 }
 pub fn print_whoami(context: &Context) {
     println!("Cluster adress : {}", context.drogue_cloud_url);
@@ -0,0 +1,211 @@
+use crate::Resources;
+use anyhow::{anyhow, Result};
+use serde_json::{json, Map, Value};
+use std::str::FromStr;
+
+// The JSON schemas of the specs, bundled with drg. They describe the sections drg knows about,
+// other sections are accepted, as the server may support more than this version of drg.
+pub fn device() -> Value {
+    json!({
+        "$schema": "http://json-schema.org/draft-07/schema#",
+        "title": "Device spec",
+        "description": "The spec of a device of the drogue cloud registry.",
+        "type": "object",
+        "properties": {
+            "credentials": {
+                "description": "The credentials the device authenticates with.",
+                "type": "object",
+                "properties": {
+                    "credentials": {
+                        "description": "The list of credentials, any of them is accepted.",
+                        "type": "array",
+                        "items": {
+                            "oneOf": [
+                                {
+                                    "type": "object",
+                                    "properties": {
+                                        "pass": {
+                                            "description": "A password, the username being the device id.",
+                                            "type": "string"
+                                        }
+                                    },
+                                    "required": ["pass"]
+                                },
+                                {
+                                    "type": "object",
+                                    "properties": {
+                                        "user": {
+                                            "description": "A username and password pair.",
+                                            "type": "object",
+                                            "properties": {
+                                                "username": {
+                                                    "description": "The username, which can differ from the device id.",
+                                                    "type": "string"
+                                                },
+                                                "password": {
+                                                    "description": "The password of this username.",
+                                                    "type": "string"
+                                                }
+                                            },
+                                            "required": ["username", "password"]
+                                        }
+                                    },
+                                    "required": ["user"]
+                                },
+                                {
+                                    "type": "object",
+                                    "properties": {
+                                        "psk": {
+                                            "description": "A pre-shared key, for TLS-PSK.",
+                                            "type": "object",
+                                            "properties": {
+                                                "key": {
+                                                    "description": "The key, encoded in base64.",
+                                                    "type": "string",
+                                                    "contentEncoding": "base64"
+                                                }
+                                            },
+                                            "required": ["key"]
+                                        }
+                                    },
+                                    "required": ["psk"]
+                                }
+                            ]
+                        }
+                    }
+                }
+            },
+            "alias": {
+                "description": "Other names of the device, such as the subject of its certificate.",
+                "type": "array",
+                "items": { "type": "string" }
+            },
+            "gatewaySelector": {
+                "description": "The gateways allowed to act on behalf of the device.",
+                "type": "object",
+                "properties": {
+                    "matchNames": {
+                        "description": "The ids of the gateway devices.",
+                        "type": "array",
+                        "items": { "type": "string" }
+                    }
+                }
+            }
+        },
+        "additionalProperties": true
+    })
+}
+
+pub fn app() -> Value {
+    json!({
+        "$schema": "http://json-schema.org/draft-07/schema#",
+        "title": "App spec",
+        "description": "The spec of an app of the drogue cloud registry.",
+        "type": "object",
+        "properties": {
+            "trustAnchors": {
+                "description": "The certificates of the authorities signing the device certificates of the app.",
+                "type": "object",
+                "properties": {
+                    "anchors": {
+                        "description": "The list of trust anchors.",
+                        "type": "array",
+                        "items": {
+                            "type": "object",
+                            "properties": {
+                                "certificate": {
+                                    "description": "The PEM encoded certificate, encoded in base64.",
+                                    "type": "string",
+                                    "contentEncoding": "base64"
+                                }
+                            },
+                            "required": ["certificate"]
+                        }
+                    }
+                }
+            }
+        },
+        "additionalProperties": true
+    })
+}
+
+// Print the description of a field given as a path such as device.spec.gatewaySelector,
+// and of the fields it contains.
+pub fn explain(path: &str) -> Result<()> {
+    let mut segments = path.split('.').peekable();
+    let resource = segments.next().unwrap_or_default();
+    let mut schema = match Resources::from_str(resource) {
+        Ok(Resources::app) | Ok(Resources::apps) => app(),
+        Ok(Resources::device) | Ok(Resources::devices) => device(),
+        Err(_) => {
+            return Err(anyhow!(
+                "Unknown resource {}, expected app or device, e.g. device.spec.alias",
+                resource
+            ))
+        }
+    };
+
+    // the path is relative to the spec, which can be omitted.
+    if segments.peek() == Some(&"spec") {
+        segments.next();
+    }
+    let mut current = format!("{}.spec", resource);
+    for segment in segments {
+        let fields = properties(&schema);
+        schema = fields.get(segment).cloned().ok_or_else(|| {
+            anyhow!(
+                "No such field {} in {}. Its fields are : {}",
+                segment,
+                current,
+                names(&fields)
+            )
+        })?;
+        current = format!("{}.{}", current, segment);
+    }
+
+    println!("FIELD:       {}", current);
+    println!("TYPE:        {}", type_name(&schema));
+    if let Some(description) = schema["description"].as_str() {
+        println!("DESCRIPTION: {}", description);
+    }
+    let fields = properties(&schema);
+    if !fields.is_empty() {
+        println!("FIELDS:");
+        for (name, field) in &fields {
+            println!(
+                "  {} <{}> {}",
+                name,
+                type_name(field),
+                field["description"].as_str().unwrap_or_default()
+            );
+        }
+    }
+    Ok(())
+}
+
+// The fields of an object, or of the items of an array, including the alternatives of a oneOf.
+fn properties(schema: &Value) -> Map<String, Value> {
+    let schema = schema.get("items").unwrap_or(schema);
+    let mut fields = Map::new();
+    let alternatives = schema["oneOf"].as_array().into_iter().flatten();
+    for s in std::iter::once(schema).chain(alternatives) {
+        if let Some(properties) = s["properties"].as_object() {
+            fields.extend(properties.clone());
+        }
+    }
+    fields
+}
+
+fn names(fields: &Map<String, Value>) -> String {
+    fields.keys().cloned().collect::<Vec<String>>().join(", ")
+}
+
+fn type_name(schema: &Value) -> String {
+    match (schema["type"].as_str(), schema.get("items")) {
+        (Some("array"), Some(items)) => {
+            format!("array of {}", items["type"].as_str().unwrap_or("object"))
+        }
+        (Some(t), _) => t.to_string(),
+        (None, _) => "object".to_string(),
+    }
+}
@@ -0,0 +1,262 @@
+use crate::config::{self, Config, Context, ContextId};
+use crate::{http, openid, util};
+use anyhow::Result;
+use chrono::Utc;
+use oauth2::TokenResponse;
+use reqwest::StatusCode;
+use std::time::{Duration, Instant};
+
+// Print the result of each check. Returns false if one of the critical checks failed.
+pub fn status(
+    config_file: Option<String>,
+    config: Result<Config>,
+    context_name: &Option<ContextId>,
+) -> bool {
+    let config_check = match &config_file {
+        Some(path) => config
+            .as_ref()
+            .map(|_| path.clone())
+            .map_err(|e| anyhow::anyhow!("{:#}", e)),
+        None => Ok("not used".to_string()),
+    };
+    report(&config_check, "Config file");
+
+    let mut config = match config {
+        Ok(config) => config,
+        Err(_) => return false,
+    };
+
+    let mut context = match config.get_context(context_name) {
+        Ok(context) => context.clone(),
+        Err(e) => return report::<String>(&Err(e), "Context"),
+    };
+    report(&Ok(&context.name), "Context");
+
+    let reachable = report(
+        &http::client()
+            .get(context.registry_url.clone())
+            .send()
+            .map(|res| format!("{} ({})", context.registry_url, res.status()))
+            .map_err(anyhow::Error::from),
+        "Registry",
+    );
+
+    // a token given with --no-config has no known expiry and can't be refreshed.
+    let valid_token = match &config_file {
+        None => report(&Ok("given by flags, expiry not checked"), "Token"),
+        Some(path) => {
+            let refreshed = openid::verify_token_validity(&mut context);
+            let valid_token = report(
+                &refreshed
+                    .as_ref()
+                    .map(|_| format!("expires at {}", context.token_exp_date))
+                    .map_err(|e| anyhow::anyhow!("{}", e)),
+                "Token",
+            );
+
+            // keep the refreshed token, the previous refresh token may not be valid anymore.
+            if let Ok(true) = refreshed {
+                let saved = config
+                    .get_context_mut(context_name)
+                    .map(|c| *c = context.clone());
+                if let Err(e) = saved.and_then(|_| config.write(Some(path.as_str()))) {
+                    log::warn!("Could not save the refreshed token : {}", e);
+                }
+            }
+            valid_token
+        }
+    };
+
+    // not critical, older servers do not expose their version.
+    report(
+        &util::get_drogue_services_version(&context.drogue_cloud_url).map(|v| format!("v{}", v)),
+        "Server version",
+    );
+
+    reachable && valid_token
+}
+
+// Like status, with more checks and advice on how to fix each failure.
+// Returns false if one of the critical checks failed.
+pub fn doctor(
+    config_file: Option<String>,
+    config: Result<Config>,
+    context_name: &Option<ContextId>,
+) -> bool {
+    let mut healthy = true;
+
+    if let Some(path) = &config_file {
+        healthy &= advise(
+            report(
+                &config
+                    .as_ref()
+                    .map(|_| path.clone())
+                    .map_err(|e| anyhow::anyhow!("{:#}", e)),
+                "Config file",
+            ),
+            "Log in with drg login <url> to create it, or fix the file. Older files are upgraded with drg context migrate.",
+        );
+        if let Some(mode) = config::open_permissions(path) {
+            report::<String>(
+                &Err(anyhow::anyhow!(
+                    "{:o}, other users can read the tokens",
+                    mode
+                )),
+                "Config permissions",
+            );
+            advise(false, &format!("Run chmod 600 {}", path));
+        }
+    }
+
+    let context = match config
+        .as_ref()
+        .map_err(|e| anyhow::anyhow!("{}", e))
+        .and_then(|c| c.get_context(context_name).map(|c| c.clone()))
+    {
+        Ok(context) => context,
+        Err(e) => {
+            report::<String>(&Err(e), "Context");
+            advise(
+                false,
+                "Log in with drg login <url>, or pick an existing context with drg context set-active.",
+            );
+            return false;
+        }
+    };
+    report(&Ok(&context.name), "Context");
+
+    let response = http::client().get(context.registry_url.clone()).send();
+    healthy &= advise(
+        report(
+            &response
+                .as_ref()
+                .map(|res| format!("{} ({})", context.registry_url, res.status()))
+                .map_err(|e| anyhow::anyhow!("{}", e)),
+            "Registry",
+        ),
+        "Check the url with drg context show, and the network or proxy settings.",
+    );
+    if let Some(skew) = response
+        .as_ref()
+        .ok()
+        .and_then(|res| http::clock_skew(res.headers()))
+    {
+        let detail = format!("{}s from the server's clock", skew);
+        advise(
+            report(
+                &if http::is_clock_skewed(skew) {
+                    Err(anyhow::anyhow!(detail))
+                } else {
+                    Ok(detail)
+                },
+                "Clock",
+            ),
+            "Sync the clock of this machine, e.g. with NTP, the token expiration checks depend on it.",
+        );
+    }
+
+    // the token is only inspected : refreshing it here without saving it would invalidate
+    // the stored refresh token.
+    let token = match config_file {
+        Some(_) => token_expiry(&context),
+        None => Ok("given by flags, expiry not checked".to_string()),
+    };
+    healthy &= advise(report(&token, "Token"), "Log in again with drg login.");
+
+    advise(
+        report(
+            &edit::get_editor()
+                .map(|e| e.display().to_string())
+                .map_err(|_| anyhow::anyhow!("none found")),
+            "Editor",
+        ),
+        "Set $EDITOR or $VISUAL to use drg edit without --filename.",
+    );
+
+    healthy
+}
+
+// When the token expires, without refreshing it.
+fn token_expiry(context: &Context) -> Result<String> {
+    if context.token_exp_date > Utc::now() {
+        Ok(format!("expires at {}", context.token_exp_date))
+    } else if context.token.refresh_token().is_some() {
+        Ok(format!(
+            "expired at {}, it is refreshed by the next command",
+            context.token_exp_date
+        ))
+    } else {
+        Err(anyhow::anyhow!(
+            "expired at {} and cannot be refreshed",
+            context.token_exp_date
+        ))
+    }
+}
+
+// Print how to fix a failed check.
+fn advise(ok: bool, advice: &str) -> bool {
+    if !ok {
+        println!("      -> {}", advice);
+    }
+    ok
+}
+
+// Time a few authenticated requests to the registry, and print a one line summary.
+// Returns false if all the requests failed or were not authorized.
+pub fn ping(context: &Context, count: usize) -> bool {
+    let url = format!("{}{}/apps", context.registry_url, util::REGISTRY_API_PATH);
+    let mut latencies: Vec<Duration> = Vec::new();
+    let mut authorized = true;
+
+    for _ in 0..count {
+        let start = Instant::now();
+        let res = http::send(context, http::client().get(&url).query(&[("limit", "1")]));
+        match res {
+            Ok(res) => {
+                latencies.push(start.elapsed());
+                if res.status() == StatusCode::UNAUTHORIZED || res.status() == StatusCode::FORBIDDEN
+                {
+                    authorized = false;
+                }
+            }
+            Err(e) => log::warn!("Request failed : {}", e),
+        }
+    }
+
+    if latencies.is_empty() {
+        println!(
+            "{} : {}/{} requests failed",
+            context.registry_url, count, count
+        );
+        return false;
+    }
+    let ms = |d: &Duration| d.as_secs_f64() * 1000.0;
+    let min = latencies.iter().map(ms).fold(f64::MAX, f64::min);
+    let max = latencies.iter().map(ms).fold(0.0, f64::max);
+    let avg = latencies.iter().map(ms).sum::<f64>() / latencies.len() as f64;
+
+    println!(
+        "{} : {}/{} requests, min {:.1}ms avg {:.1}ms max {:.1}ms, auth {}",
+        context.registry_url,
+        latencies.len(),
+        count,
+        min,
+        avg,
+        max,
+        if authorized { "ok" } else { "failed" }
+    );
+    authorized
+}
+
+fn report<T: std::fmt::Display>(result: &Result<T>, check: &str) -> bool {
+    match result {
+        Ok(detail) => {
+            println!("OK    {} : {}", check, detail);
+            true
+        }
+        Err(e) => {
+            println!("FAIL  {} : {}", check, e);
+            false
+        }
+    }
+}
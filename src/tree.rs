@@ -0,0 +1,56 @@
+use crate::config::Context;
+use crate::{apps, devices};
+use anyhow::Result;
+use serde_json::Value;
+
+// Print the apps, with their devices nested beneath them when depth is 2.
+// Only the first `width` apps, and devices of each app, are printed.
+pub fn print(config: &Context, depth: usize, width: Option<usize>) -> Result<()> {
+    let (apps, more_apps) = truncate(names(apps::get_list(config, None, false)?), width);
+
+    for app in apps {
+        println!("{}", app);
+        if depth < 2 {
+            continue;
+        }
+        let (devices, more_devices) =
+            truncate(names(devices::get_list(config, &app, None, false)?), width);
+        for line in branches(&devices, more_devices) {
+            println!("{}", line);
+        }
+    }
+    if more_apps > 0 {
+        println!("... {} more app(s)", more_apps);
+    }
+    Ok(())
+}
+
+fn names(resources: Vec<Value>) -> Vec<String> {
+    resources
+        .iter()
+        .filter_map(|r| r["metadata"]["name"].as_str().map(|n| n.to_string()))
+        .collect()
+}
+
+// The first `width` names, and how many were left out.
+fn truncate(mut names: Vec<String>, width: Option<usize>) -> (Vec<String>, usize) {
+    let more = match width {
+        Some(width) if names.len() > width => names.len() - width,
+        _ => 0,
+    };
+    names.truncate(names.len() - more);
+    (names, more)
+}
+
+fn branches(children: &[String], more: usize) -> Vec<String> {
+    let mut lines: Vec<String> = children
+        .iter()
+        .map(|child| format!("├── {}", child))
+        .collect();
+    if more > 0 {
+        lines.push(format!("└── ... {} more device(s)", more));
+    } else if let Some(last) = lines.last_mut() {
+        *last = last.replacen("├──", "└──", 1);
+    }
+    lines
+}
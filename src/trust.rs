@@ -232,6 +232,20 @@ pub fn verify_input_key(key_input: &str) -> Result<(KeyPair, SignAlgo)> {
     Ok((key, alg))
 }
 
+// The PEM certificate of a trust anchor, stored base64 encoded in the app.
+pub fn decode_anchor(certificate: &str) -> Result<String> {
+    let pem = base64::decode(certificate.trim())
+        .map_err(|e| anyhow!("Invalid trust anchor, not base64 : {}", e))?;
+    let pem = String::from_utf8(pem).map_err(|_| anyhow!("Invalid trust anchor, not PEM"))?;
+
+    let x509 = x509_parser::pem::parse_x509_pem(pem.as_bytes())
+        .map_err(|e| anyhow!("Invalid trust anchor, not PEM : {}", e))?
+        .1;
+    x509_parser::parse_x509_certificate(&x509.contents)
+        .map_err(|e| anyhow!("Invalid trust anchor certificate : {}", e))?;
+    Ok(pem)
+}
+
 fn verify_public_key(ca_cert: &str, local_cert: &[u8]) -> Result<()> {
     let ca_x509 = x509_parser::pem::parse_x509_pem(&ca_cert.as_bytes())?.1;
     let ca_x509_der = x509_parser::parse_x509_certificate(&ca_x509.contents)?.1;
@@ -315,6 +329,14 @@ mod trust_test {
         );
     }
 
+    #[test]
+    fn test_decode_anchor() {
+        let pem = decode_anchor(CERT).unwrap();
+        assert!(pem.starts_with("-----BEGIN CERTIFICATE-----"));
+        assert!(decode_anchor("not base64").is_err());
+        assert!(decode_anchor(&encode("not a certificate")).is_err());
+    }
+
     #[test]
     fn test_create_device_certificate() {
         assert!(
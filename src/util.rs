@@ -1,21 +1,26 @@
 use crate::config::{Config, Context};
+use crate::http::{self, Response};
 use crate::Other_flags;
+use crate::Outputs;
 use crate::Verbs;
 use anyhow::{anyhow, Context as AnyhowContext, Result};
-use chrono::{Duration, Utc};
+use chrono::{DateTime, Duration, Utc};
 use clap::crate_version;
 use clap::ArgMatches;
 use colored_json::write_colored_json;
 use log::LevelFilter;
-use oauth2::TokenResponse;
-use reqwest::blocking::{Client, Response};
 use reqwest::StatusCode;
+use serde::de::{self, Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
 use serde_json::Value::String as serde_string;
 use serde_json::{from_str, Value};
+use std::cmp::Ordering;
 use std::fs;
 use std::io::stdout;
 use std::io::{Read, Write};
+use std::path::Path;
 use std::process::exit;
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use std::time::Duration as StdDuration;
 use tabular::{Row, Table};
 use tempfile::Builder;
 use url::Url;
@@ -24,28 +29,831 @@ pub const VERSION: &str = crate_version!();
 pub const COMPATIBLE_DROGUE_VERSION: &str = "0.5.0";
 pub const REGISTRY_API_PATH: &str = "api/registry/v1alpha1";
 pub const COMMAND_API_PATH: &str = "api/command/v1alpha1";
+pub const ADMIN_API_PATH: &str = "api/admin/v1alpha1";
+pub const DEFAULT_COLUMNS: &[&str] = &["metadata.name", "metadata.creationTimestamp"];
+pub const WIDE_COLUMNS: &[&str] = &[
+    "metadata.name",
+    "metadata.creationTimestamp",
+    "metadata.generation",
+    "metadata.labels",
+];
+pub const ENV_COLUMNS: &[&str] = &[
+    "metadata.name",
+    "metadata.application",
+    "metadata.creationTimestamp",
+    "metadata.generation",
+    "metadata.resourceVersion",
+];
+
+// Whether environment variables are expanded in data files, see --no-env-subst.
+static ENV_SUBST: AtomicBool = AtomicBool::new(true);
+// Whether notices are hidden, see --quiet.
+static QUIET: AtomicBool = AtomicBool::new(false);
+// Whether the app used by default was already announced.
+static APP_NOTICE: AtomicBool = AtomicBool::new(false);
+// Whether duplicate keys are rejected in JSON data, see --strict-json.
+static STRICT_JSON: AtomicBool = AtomicBool::new(false);
 
 pub fn print_result(r: Response, resource_name: String, op: Verbs) {
-    match op {
-        Verbs::create => match r.status() {
-            StatusCode::CREATED => println!("{} created.", resource_name),
-            r => exit_with_code(r),
-        },
-        Verbs::delete => match r.status() {
-            StatusCode::NO_CONTENT => println!("{} deleted.", resource_name),
-            r => exit_with_code(r),
-        },
-        Verbs::get => match r.status() {
-            StatusCode::OK => show_json(r.text().expect("Empty response")),
-            r => exit_with_code(r),
-        },
-        Verbs::edit | Verbs::set => match r.status() {
-            StatusCode::NO_CONTENT => println!("{} updated.", resource_name),
-            r => exit_with_code(r),
-        },
+    match expected_status(op) {
+        Some(status) if r.status() == status => {}
+        Some(_) => exit_with_code(r.status()),
         //should never happen.
-        Verbs::cmd => {}
+        None => return,
+    }
+    let text = String::from_utf8_lossy(r.bytes()).trim().to_string();
+    match result_message(&text, &resource_name, op) {
+        Some(message) => println!("{}", message),
+        None => show_json(text),
+    }
+}
+
+fn expected_status(op: Verbs) -> Option<StatusCode> {
+    match op {
+        Verbs::create => Some(StatusCode::CREATED),
+        Verbs::delete => Some(StatusCode::NO_CONTENT),
+        Verbs::get => Some(StatusCode::OK),
+        Verbs::edit | Verbs::set | Verbs::rollback => Some(StatusCode::NO_CONTENT),
+        Verbs::cmd | Verbs::transfer | Verbs::accept | Verbs::label | Verbs::annotate => None,
+    }
+}
+
+// Only print the id of a created resource, for `-o id`. The name returned by the server wins,
+// then the one of the sent resource, as a file may name it differently than the command line.
+pub fn print_id(r: Response, sent: &Value, id: &str) {
+    if !r.status().is_success() {
+        exit_with_code(r.status());
+    }
+    let returned: Option<Value> = serde_json::from_slice(r.bytes()).ok();
+    let name = returned
+        .as_ref()
+        .and_then(|v| v["metadata"]["name"].as_str())
+        .or_else(|| sent["metadata"]["name"].as_str())
+        .unwrap_or(id);
+    println!("{}", name);
+}
+
+// The message for a successful response, or None when the body is the resource to show.
+// The body is only parsed when it's JSON : empty bodies, such as the ones of 204 responses,
+// and plain text bodies are fine.
+fn result_message(text: &str, resource_name: &str, op: Verbs) -> Option<String> {
+    let message = match op {
+        Verbs::get if text.is_empty() => return Some(format!("{} has no content.", resource_name)),
+        Verbs::get => return None,
+        Verbs::create => format!("{} created.", resource_name),
+        Verbs::delete => format!("{} deleted.", resource_name),
+        Verbs::edit | Verbs::set | Verbs::rollback => format!("{} updated.", resource_name),
+        _ => return None,
+    };
+
+    // the body of a JSON response is the resource, a text body is a message from the server.
+    if text.is_empty() || serde_json::from_str::<Value>(text).is_ok() {
+        Some(message)
+    } else {
+        Some(format!("{}\n{}", message, text))
+    }
+}
+
+// The update is rejected by the server if the resource was modified since this version.
+pub fn set_resource_version(resource: &mut Value, version: &str) -> Result<()> {
+    let metadata = resource
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("The resource must be an object."))?
+        .entry("metadata")
+        .or_insert_with(|| Value::Object(Default::default()));
+    metadata
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("The metadata of the resource must be an object."))?
+        .insert(
+            "resourceVersion".to_string(),
+            Value::String(version.to_string()),
+        );
+    Ok(())
+}
+
+// The update of a resource was rejected because it was modified since the version the update is based on.
+pub fn conflict_error(resource_name: String, current: &Value) -> anyhow::Error {
+    anyhow!(
+        "{} was modified in the meantime. Its current resource version is {}.",
+        resource_name,
+        current["metadata"]["resourceVersion"]
+            .as_str()
+            .unwrap_or("unknown")
+    )
+}
+
+// Where and how the results of a command are printed.
+#[derive(Default)]
+pub struct Output<'a> {
+    pub format: Option<Outputs>,
+    pub file: Option<&'a str>,
+    pub sort_by: Option<&'a str>,
+    pub columns: Vec<&'a str>,
+    pub only: Option<&'a str>,
+    // tables show the age of the resources unless set.
+    pub full_timestamps: bool,
+    // lists are requested page by page, with this page size, when set.
+    pub chunk_size: Option<usize>,
+    // only keep the resources created in this window.
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    // labels shown as extra columns of tables.
+    pub label_columns: Vec<&'a str>,
+    // the variables of the env output start with it.
+    pub env_prefix: Option<&'a str>,
+    // the response is written to this file, with an extension matching its content type.
+    pub save: Option<&'a str>,
+    // copy the result instead of printing it.
+    pub clipboard: bool,
+    // each resource is rendered through this template, read from --output-template-file.
+    pub template: Option<String>,
+}
+
+impl Output<'_> {
+    // Whether the human readable output is printed. It only picks the format and destination,
+    // what is shown, e.g. redacted secrets, is decided before.
+    pub fn is_default(&self) -> bool {
+        self.format.is_none()
+            && self.file.is_none()
+            && self.columns.is_empty()
+            && self.label_columns.is_empty()
+            && self.only.is_none()
+            && self.save.is_none()
+            && !self.clipboard
+            && self.template.is_none()
+    }
+
+    // Lists can only be printed page by page when nothing needs the whole list first.
+    pub fn is_streamed(&self) -> bool {
+        self.format == Some(Outputs::jsonl) && self.file.is_none() && self.sort_by.is_none()
+    }
+
+    pub fn sort(&self, resources: &mut [Value]) {
+        if let Some(field) = self.sort_by {
+            let pointer = field_pointer(field);
+            resources.sort_by(|a, b| compare_fields(a.pointer(&pointer), b.pointer(&pointer)));
+        }
+    }
+
+    pub fn filter(&self, resources: Vec<Value>) -> Vec<Value> {
+        if self.since.is_none() && self.until.is_none() {
+            return resources;
+        }
+        resources
+            .into_iter()
+            .filter(|r| {
+                match r["metadata"]["creationTimestamp"]
+                    .as_str()
+                    .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+                    .map(|t| t.with_timezone(&Utc))
+                {
+                    Some(created) => {
+                        self.since.map_or(true, |since| created >= since)
+                            && self.until.map_or(true, |until| created <= until)
+                    }
+                    None => false,
+                }
+            })
+            .collect()
+    }
+
+    pub fn resource(&self, resource: &Value) -> Result<()> {
+        if let Some(only) = self.only {
+            return self.write_lines(vec![field(resource, only)]);
+        }
+        if let Some(template) = &self.template {
+            let rendered = render_template(template, resource)?;
+            return self.write(rendered.strip_suffix('\n').unwrap_or(&rendered).to_string());
+        }
+        match self.format {
+            Some(Outputs::json_compact) | Some(Outputs::jsonl) => {
+                self.write(serde_json::to_string(resource)?)
+            }
+            Some(Outputs::name) => self.write(resource_name(resource)),
+            Some(Outputs::tsv) => self.write(self.tsv(std::slice::from_ref(resource))),
+            Some(Outputs::wide) => self.write(self.table(std::slice::from_ref(resource))),
+            Some(Outputs::env) => self.write(self.env(resource)),
+            Some(Outputs::pem) => Err(anyhow!("The pem output is only available for a device.")),
+            _ => self.write(serde_json::to_string_pretty(resource)?),
+        }
+    }
+
+    pub fn list(&self, resources: &[Value]) -> Result<()> {
+        if let Some(only) = self.only {
+            return self.write_lines(resources.iter().map(|r| field(r, only)).collect());
+        }
+        if let Some(template) = &self.template {
+            let rendered = resources
+                .iter()
+                .map(|r| {
+                    render_template(template, r)
+                        .map(|t| t.strip_suffix('\n').unwrap_or(&t).to_string())
+                })
+                .collect::<Result<Vec<String>>>()?;
+            return self.write(rendered.join("\n"));
+        }
+        match self.format {
+            Some(Outputs::json_compact) | Some(Outputs::jsonl) => {
+                let lines = resources
+                    .iter()
+                    .map(serde_json::to_string)
+                    .collect::<serde_json::Result<Vec<String>>>()?;
+                self.write(lines.join("\n"))
+            }
+            Some(Outputs::name) => {
+                let names: Vec<String> = resources.iter().map(resource_name).collect();
+                self.write(names.join("\n"))
+            }
+            Some(Outputs::tsv) => self.write(self.tsv(resources)),
+            Some(Outputs::wide) => self.write(self.table(resources)),
+            Some(Outputs::env) => Err(anyhow!(
+                "The env output needs a single resource, the variables would clash."
+            )),
+            Some(Outputs::pem) => Err(anyhow!("The pem output is only available for a device.")),
+            None if !self.columns.is_empty() || !self.label_columns.is_empty() => {
+                self.write(self.table(resources))
+            }
+            _ => self.write(serde_json::to_string_pretty(resources)?),
+        }
+    }
+
+    fn columns(&self) -> &[&str] {
+        if !self.columns.is_empty() {
+            &self.columns
+        } else if self.format == Some(Outputs::wide) {
+            WIDE_COLUMNS
+        } else {
+            DEFAULT_COLUMNS
+        }
+    }
+
+    // A header row with the column names, then one row per resource.
+    fn tsv(&self, resources: &[Value]) -> String {
+        let columns = self.columns();
+        let mut lines = vec![columns
+            .iter()
+            .chain(&self.label_columns)
+            .copied()
+            .collect::<Vec<&str>>()
+            .join("\t")];
+        for resource in resources {
+            let cells: Vec<String> = fields(resource, columns)
+                .into_iter()
+                .chain(labels(resource, &self.label_columns))
+                .map(|f| f.replace(&['\t', '\n'][..], " "))
+                .collect();
+            lines.push(cells.join("\t"));
+        }
+        lines.join("\n")
+    }
+
+    // One shell assignment per field and label, e.g. DEVICE_NAME='foo'.
+    // Without --env-prefix, the variables start with DEVICE or APP.
+    fn env(&self, resource: &Value) -> String {
+        let columns: &[&str] = if self.columns.is_empty() {
+            ENV_COLUMNS
+        } else {
+            &self.columns
+        };
+        let prefix = self.env_prefix.unwrap_or_else(|| {
+            if resource["metadata"]["application"].is_string() {
+                "DEVICE"
+            } else {
+                "APP"
+            }
+        });
+
+        let fields = columns.iter().filter_map(|c| {
+            resource
+                .pointer(&field_pointer(c))
+                .filter(|v| !v.is_null())
+                .map(|v| {
+                    (
+                        env_name(prefix, c.rsplit('.').next().unwrap_or(*c)),
+                        env_value(v),
+                    )
+                })
+        });
+        let label_variables = self.label_columns.iter().map(|l| {
+            (
+                env_name(prefix, &format!("label.{}", l)),
+                labels(resource, &[*l]).remove(0),
+            )
+        });
+        fields
+            .chain(label_variables)
+            .map(|(name, value)| format!("{}={}", name, shell_quote(&value)))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    fn table(&self, resources: &[Value]) -> String {
+        let columns = self.columns();
+        let width = columns.len() + self.label_columns.len();
+        let mut table = Table::new(&vec!["{:<}"; width].join(" "));
+        let header = columns.iter().fold(Row::new(), |row, c| {
+            if !self.full_timestamps && *c == "metadata.creationTimestamp" {
+                row.with_cell("AGE")
+            } else {
+                row.with_cell(c.to_uppercase())
+            }
+        });
+        table.add_row(
+            self.label_columns
+                .iter()
+                .fold(header, |row, l| row.with_cell(l.to_uppercase())),
+        );
+        for resource in resources {
+            let row = columns.iter().fold(Row::new(), |row, c| {
+                row.with_cell(table_cell(resource, c, self.full_timestamps))
+            });
+            table.add_row(
+                labels(resource, &self.label_columns)
+                    .into_iter()
+                    .fold(row, |row, l| row.with_cell(l)),
+            );
+        }
+        // the table ends with a new line, which write adds back.
+        table.to_string().trim_end().to_string()
+    }
+
+    // Unlike write, empty lines are kept.
+    fn write_lines(&self, lines: Vec<String>) -> Result<()> {
+        match self.file {
+            Some(f) => write_file_atomic(f, &lines.join("\n")),
+            None if self.clipboard => {
+                print_or_copy(&lines.join("\n"), true);
+                Ok(())
+            }
+            None => {
+                for line in lines {
+                    println!("{}", line);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    pub fn write(&self, content: String) -> Result<()> {
+        match self.file {
+            Some(f) => write_file_atomic(f, &content),
+            None if self.clipboard => {
+                print_or_copy(&content, true);
+                Ok(())
+            }
+            None => {
+                if !content.is_empty() {
+                    println!("{}", content);
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+pub fn resource_name(resource: &Value) -> String {
+    resource["metadata"]["name"]
+        .as_str()
+        .unwrap_or_default()
+        .to_string()
+}
+
+// A variable name from a field name : fooBar and foo-bar become PREFIX_FOO_BAR.
+fn env_name(prefix: &str, field: &str) -> String {
+    let mut name = String::new();
+    let mut previous_lower = false;
+    for c in field.chars() {
+        if c.is_ascii_uppercase() && previous_lower {
+            name.push('_');
+        }
+        previous_lower = c.is_ascii_lowercase() || c.is_ascii_digit();
+        name.push(if c.is_ascii_alphanumeric() {
+            c.to_ascii_uppercase()
+        } else {
+            '_'
+        });
+    }
+    let prefix = prefix.trim_end_matches('_');
+    if prefix.is_empty() {
+        name
+    } else {
+        format!("{}_{}", prefix, name)
+    }
+}
+
+// Arrays of strings or numbers are joined with commas, other values are printed as with --only.
+fn env_value(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Array(items) if items.iter().all(|i| !i.is_object() && !i.is_array()) => items
+            .iter()
+            .map(|i| {
+                i.as_str()
+                    .map(|s| s.to_string())
+                    .unwrap_or_else(|| i.to_string())
+            })
+            .collect::<Vec<String>>()
+            .join(","),
+        v => v.to_string(),
+    }
+}
+
+// Single quoted for a POSIX shell, so nothing in the value is expanded.
+pub fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+fn field_pointer(path: &str) -> String {
+    format!("/{}", path.replace('.', "/"))
+}
+
+// The value of a dotted field path, strings unquoted. Missing or null fields are empty.
+pub fn field(resource: &Value, path: &str) -> String {
+    match resource.pointer(&field_pointer(path)) {
+        None | Some(Value::Null) => String::new(),
+        Some(Value::String(s)) => s.clone(),
+        Some(v) => v.to_string(),
+    }
+}
+
+// KEY=VALUE sets a key of a metadata map, KEY- removes it, as with kubectl label.
+pub fn parse_metadata_changes(changes: &[&str]) -> Result<Vec<(String, Option<String>)>> {
+    changes
+        .iter()
+        .map(|change| match change.split_once('=') {
+            Some((key, _)) if key.is_empty() => Err(anyhow!("Missing key in \"{}\"", change)),
+            Some((key, value)) => Ok((key.to_string(), Some(value.to_string()))),
+            None => match change.strip_suffix('-') {
+                Some(key) if !key.is_empty() => Ok((key.to_string(), None)),
+                _ => Err(anyhow!(
+                    "Invalid change \"{}\", expected KEY=VALUE or KEY-",
+                    change
+                )),
+            },
+        })
+        .collect()
+}
+
+// Values given as @FILE are replaced by the content of the file.
+pub fn read_value_files(changes: &mut [(String, Option<String>)]) -> Result<()> {
+    for (key, value) in changes.iter_mut() {
+        if let Some(path) = value.as_ref().and_then(|v| v.strip_prefix('@')) {
+            let content = fs::read_to_string(path)
+                .context(format!("Cannot read the value of {} from {}", key, path))?;
+            *value = Some(content);
+        }
+    }
+    Ok(())
+}
+
+// Apply the changes to a metadata map, such as the labels, creating it if needed.
+pub fn apply_metadata_changes(map: &mut Value, changes: &[(String, Option<String>)]) {
+    if !map.is_object() {
+        *map = Value::Object(Default::default());
+    }
+    if let Some(map) = map.as_object_mut() {
+        for (key, value) in changes {
+            match value {
+                Some(value) => map.insert(key.clone(), Value::String(value.clone())),
+                None => map.remove(key),
+            };
+        }
+    }
+}
+
+// Replace the {field} placeholders of a template by the metadata fields of the resource.
+pub fn expand_template(template: &str, resource: &Value) -> Result<String> {
+    expand(template, |name| {
+        let value = field(resource, &format!("metadata.{}", name));
+        if value.is_empty() {
+            return Err(anyhow!(
+                "{} has no metadata field {} for the template {}",
+                resource_name(resource),
+                name,
+                template
+            ));
+        }
+        Ok(value)
+    })
+}
+
+// Replace the {dotted.path} placeholders of an output template by the fields of the resource.
+// Missing fields are left empty, as in the columns.
+pub fn render_template(template: &str, resource: &Value) -> Result<String> {
+    expand(template, |name| Ok(field(resource, name.trim())))
+}
+
+// Read an output template, checking it before any request is made.
+pub fn read_template(path: &str) -> Result<String> {
+    let template =
+        fs::read_to_string(path).context(format!("Cannot read the template {}", path))?;
+    check_template(&template).context(format!("Invalid template {}", path))?;
+    Ok(template)
+}
+
+pub fn check_template(template: &str) -> Result<&str> {
+    expand(template, |name| {
+        if name.trim().is_empty() {
+            Err(anyhow!("Empty placeholder in the template {}", template))
+        } else {
+            Ok(String::new())
+        }
+    })?;
+    Ok(template)
+}
+
+fn expand(template: &str, mut value: impl FnMut(&str) -> Result<String>) -> Result<String> {
+    let mut expanded = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..]
+            .find('}')
+            .map(|end| start + end)
+            .ok_or_else(|| anyhow!("Unclosed placeholder in the template {}", template))?;
+        expanded.push_str(&rest[..start]);
+        expanded.push_str(&value(&rest[start + 1..end])?);
+        rest = &rest[end + 1..];
     }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+// The values of these labels of a resource, empty when a label is missing.
+// Labels can contain dots, so they are not read as dotted paths.
+fn labels(resource: &Value, labels: &[&str]) -> Vec<String> {
+    labels
+        .iter()
+        .map(|l| {
+            resource["metadata"]["labels"][*l]
+                .as_str()
+                .unwrap_or_default()
+                .to_string()
+        })
+        .collect()
+}
+
+// The cells of a row, in the order of the columns.
+pub fn fields(resource: &Value, columns: &[&str]) -> Vec<String> {
+    columns.iter().map(|c| field(resource, c)).collect()
+}
+
+// Like field, but maps such as labels are shown as key=value pairs, and timestamps as an age.
+fn table_cell(resource: &Value, path: &str, full_timestamps: bool) -> String {
+    match resource.pointer(&field_pointer(path)) {
+        Some(Value::String(ts)) if !full_timestamps && path.ends_with("Timestamp") => {
+            age(ts).unwrap_or_else(|_| ts.clone())
+        }
+        Some(Value::Object(map)) => map
+            .iter()
+            .map(|(k, v)| match v {
+                Value::String(v) => format!("{}={}", k, v),
+                v => format!("{}={}", k, v),
+            })
+            .collect::<Vec<String>>()
+            .join(","),
+        _ => field(resource, path),
+    }
+}
+
+// Missing or null fields are sorted last.
+fn compare_fields(a: Option<&Value>, b: Option<&Value>) -> Ordering {
+    match (a.filter(|v| !v.is_null()), b.filter(|v| !v.is_null())) {
+        (Some(Value::Number(a)), Some(Value::Number(b))) => a
+            .as_f64()
+            .partial_cmp(&b.as_f64())
+            .unwrap_or(Ordering::Equal),
+        (Some(Value::String(a)), Some(Value::String(b))) => a.cmp(b),
+        (Some(a), Some(b)) => a.to_string().cmp(&b.to_string()),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+// The query parameters of a list request.
+pub fn list_query(labels: Option<String>, include_deleted: bool) -> Vec<(&'static str, String)> {
+    let mut query = Vec::new();
+    if let Some(labels) = labels {
+        query.push(("labels", labels));
+    }
+    if include_deleted {
+        query.push(("includeDeleted", "true".to_string()));
+    }
+    query
+}
+
+// Resources pending deletion are only returned with --include-deleted. It is shown on stderr,
+// as the other outputs must stay parseable.
+pub fn deletion_notice(name: &str, resource: &Value) {
+    if let Some(since) = resource["metadata"]["deletionTimestamp"].as_str() {
+        eprintln!("{} is pending deletion since {}.", name, since);
+    }
+}
+
+// Request a list of resources page by page, handing each page to `f` as soon as it is received.
+// Without a page size, the server's own page size is used, as learned from the first page.
+pub fn list_paginated<F>(
+    context: &Context,
+    url: &str,
+    query: &[(&str, String)],
+    mut page_size: Option<usize>,
+    mut f: F,
+) -> Result<()>
+where
+    F: FnMut(Vec<Value>) -> Result<()>,
+{
+    let client = http::client();
+    let mut offset = 0;
+    let mut first: Option<Value> = None;
+
+    loop {
+        let mut req = client.get(url).query(&[("offset", offset)]).query(query);
+        if let Some(size) = page_size {
+            req = req.query(&[("limit", size)]);
+        }
+
+        let res = http::send(context, req).context("Can't list resources")?;
+        let page: Vec<Value> = match res.status() {
+            StatusCode::OK => res.json()?,
+            s => exit_with_code(s),
+        };
+
+        // a server ignoring the offset would send the same page again.
+        if offset > 0 && page.first() == first.as_ref() {
+            return Ok(());
+        }
+        first = page.first().cloned();
+
+        let count = page.len();
+        let size = *page_size.get_or_insert(count);
+        f(page)?;
+
+        // the last page is smaller than requested, a bigger one means the server does not paginate.
+        if count == 0 || count != size {
+            return Ok(());
+        }
+        offset += count;
+    }
+}
+
+// The whole list, requested page by page.
+pub fn list_all(
+    context: &Context,
+    url: &str,
+    query: &[(&str, String)],
+    page_size: Option<usize>,
+) -> Result<Vec<Value>> {
+    let mut resources = Vec::new();
+    list_paginated(context, url, query, page_size, |page| {
+        resources.extend(page);
+        Ok(())
+    })?;
+    Ok(resources)
+}
+
+// A deleted resource is only removed once its finalizers are done.
+// Show the pending finalizers, or remove them to force the deletion.
+pub fn finalize_deletion(
+    context: &Context,
+    url: &str,
+    resource_name: &str,
+    force: bool,
+) -> Result<()> {
+    let client = http::client();
+    let res = http::send(context, client.get(url))?;
+    if res.status() != StatusCode::OK {
+        return Ok(());
+    }
+
+    let mut resource: Value = res.json()?;
+    let finalizers = resource["metadata"]["finalizers"]
+        .as_array()
+        .map(|f| {
+            f.iter()
+                .filter_map(|f| f.as_str())
+                .collect::<Vec<&str>>()
+                .join(", ")
+        })
+        .unwrap_or_default();
+    if finalizers.is_empty() {
+        return Ok(());
+    }
+
+    if !force {
+        println!(
+            "{} is pending deletion, waiting for finalizers : {}. Use --force --yes to remove them.",
+            resource_name, finalizers
+        );
+        return Ok(());
+    }
+
+    resource["metadata"]["finalizers"] = serde_json::json!([]);
+    let req = client
+        .put(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(resource.to_string());
+
+    let res = http::send(context, req).context("Can't remove the finalizers.")?;
+    match res.status() {
+        StatusCode::OK | StatusCode::NO_CONTENT => {
+            println!(
+                "Removed the finalizers of {} : {}",
+                resource_name, finalizers
+            );
+            Ok(())
+        }
+        s => exit_with_code(s),
+    }
+}
+
+// Print the body of a successful response.
+pub fn print_body(r: Response, output: &Output) -> Result<()> {
+    match r.status() {
+        StatusCode::OK if output.save.is_some() => {
+            save(output.save.unwrap_or_default(), r.extension(), r.bytes())
+        }
+        StatusCode::OK if output.is_default() => {
+            show_json(r.text()?);
+            Ok(())
+        }
+        StatusCode::OK => output.resource(&r.json()?),
+        s => exit_with_code(s),
+    }
+}
+
+// The commands copying their standard input to the clipboard, tried in this order.
+const CLIPBOARD_COMMANDS: &[&[&str]] = &[
+    &["pbcopy"],
+    &["wl-copy"],
+    &["xclip", "-selection", "clipboard"],
+    &["xsel", "--clipboard", "--input"],
+    &["clip.exe"],
+];
+
+// With `clipboard`, copy the content to the system clipboard, using the usual clipboard tools.
+// When none of them works, e.g. on a headless machine, the content is printed instead.
+pub fn print_or_copy(content: &str, clipboard: bool) {
+    if clipboard {
+        match copy_to_clipboard(content) {
+            Ok(()) => {
+                eprintln!("Copied to the clipboard.");
+                return;
+            }
+            Err(e) => eprintln!("Warning: {}, printing instead.", e),
+        }
+    }
+    println!("{}", content);
+}
+
+fn copy_to_clipboard(content: &str) -> Result<()> {
+    for command in CLIPBOARD_COMMANDS {
+        let child = std::process::Command::new(command[0])
+            .args(&command[1..])
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .spawn();
+        let mut child = match child {
+            Ok(child) => child,
+            Err(_) => continue,
+        };
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(content.as_bytes())?;
+        }
+        if child.wait()?.success() {
+            return Ok(());
+        }
+        log::debug!("{} could not copy to the clipboard", command[0]);
+    }
+    Err(anyhow!("No clipboard available"))
+}
+
+// Write a response to `base` with the given extension, for --save.
+pub fn save(base: &str, extension: &str, content: &[u8]) -> Result<()> {
+    let path = format!("{}.{}", base, extension);
+    write_file_atomic(&path, content)?;
+    println!("Saved to {}", path);
+    Ok(())
+}
+
+// The content is written to a temporary file next to `path`, which is then renamed,
+// so `path` never contains a partially written result.
+pub fn write_file_atomic(path: &str, content: impl AsRef<[u8]>) -> Result<()> {
+    let dir = Path::new(path)
+        .parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+
+    let mut file = Builder::new().tempfile_in(dir).context(format!(
+        "Unable to create a temporary file in {}",
+        dir.display()
+    ))?;
+    file.write_all(content.as_ref())?;
+    file.persist(path)
+        .context(format!("Unable to write file {}", path))?;
+
+    log::info!("Result written to {}", path);
+    Ok(())
 }
 
 pub fn show_json<S: Into<String>>(payload: S) {
@@ -61,6 +869,20 @@ pub fn show_json<S: Into<String>>(payload: S) {
     }
 }
 
+// Exit codes of bulk operations, when only some or none of the resources succeeded.
+pub const PARTIAL_FAILURE_EXIT_CODE: i32 = 5;
+pub const TOTAL_FAILURE_EXIT_CODE: i32 = 6;
+
+pub fn bulk_exit_code(succeeded: usize, total: usize) -> i32 {
+    if succeeded == total {
+        0
+    } else if succeeded == 0 {
+        TOTAL_FAILURE_EXIT_CODE
+    } else {
+        PARTIAL_FAILURE_EXIT_CODE
+    }
+}
+
 pub fn exit_with_code(r: reqwest::StatusCode) -> ! {
     log::error!("Error : {}", r);
     if r.as_u16() == 403 {
@@ -69,6 +891,99 @@ pub fn exit_with_code(r: reqwest::StatusCode) -> ! {
     exit(2)
 }
 
+// A token stored in a file, without the trailing line break.
+pub fn read_token_file(path: &str) -> Result<String> {
+    let token = fs::read_to_string(path).context(format!("Cannot read the token file {}", path))?;
+    let token = token.trim_end();
+    if token.is_empty() {
+        return Err(anyhow!("The token file {} is empty", path));
+    }
+    Ok(token.to_string())
+}
+
+// A secret passed on the standard input, without the line break.
+pub fn read_stdin_line() -> Result<String> {
+    let mut line = String::new();
+    std::io::stdin()
+        .read_line(&mut line)
+        .context("Cannot read the standard input")?;
+    Ok(line.trim_end_matches(&['\r', '\n'][..]).to_string())
+}
+
+// Ask a yes/no question on the terminal. Anything but y or yes is a no, so is a missing terminal.
+pub fn confirm(question: &str) -> Result<bool> {
+    if !atty::is(atty::Stream::Stdin) {
+        return Ok(false);
+    }
+
+    eprint!("{} [y/N] ", question);
+    std::io::stderr().flush()?;
+
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+// Whether a person is there to answer a prompt and read the result.
+pub fn is_interactive() -> bool {
+    atty::is(atty::Stream::Stdin) && atty::is(atty::Stream::Stdout)
+}
+
+// Let the user pick one of the names on the terminal, by its number or by typing some of its letters.
+// An empty answer cancels.
+pub fn pick(names: Vec<String>, what: &str) -> Result<Option<String>> {
+    let mut candidates = names;
+    loop {
+        match candidates.len() {
+            0 => return Err(anyhow!("No {} to choose from.", what)),
+            1 => return Ok(candidates.pop()),
+            _ => {}
+        }
+        for (i, name) in candidates.iter().enumerate() {
+            eprintln!("{:>3}) {}", i + 1, name);
+        }
+        eprint!("Select a {} (number or part of the name) : ", what);
+        std::io::stderr().flush()?;
+
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer)?;
+        let answer = answer.trim();
+        if answer.is_empty() {
+            return Ok(None);
+        }
+        if let Some(name) = answer
+            .parse::<usize>()
+            .ok()
+            .and_then(|n| candidates.get(n.wrapping_sub(1)))
+        {
+            return Ok(Some(name.clone()));
+        }
+        if let Some(name) = candidates.iter().find(|c| c.as_str() == answer) {
+            return Ok(Some(name.clone()));
+        }
+
+        let matching: Vec<String> = candidates
+            .iter()
+            .filter(|c| fuzzy_match(c, answer))
+            .cloned()
+            .collect();
+        if matching.is_empty() {
+            eprintln!("No {} matches \"{}\".", what, answer);
+        } else {
+            candidates = matching;
+        }
+    }
+}
+
+// The letters of the pattern appear in the name in the same order, ignoring the case.
+fn fuzzy_match(name: &str, pattern: &str) -> bool {
+    let mut letters = name.chars().flat_map(char::to_lowercase);
+    pattern
+        .chars()
+        .flat_map(char::to_lowercase)
+        .all(|p| letters.any(|l| l == p))
+}
+
 pub fn url_validation(url: &str) -> Result<Url> {
     Url::parse(url).or_else(|_| {
         Url::parse(&format!("https://{}", url))
@@ -77,14 +992,100 @@ pub fn url_validation(url: &str) -> Result<Url> {
 }
 
 pub fn json_parse(data: Option<&str>) -> Result<Value> {
-    from_str(data.unwrap_or("{}")).context(format!(
+    match data.and_then(|d| d.strip_prefix('@')) {
+        Some(url) if url.starts_with("http://") || url.starts_with("https://") => {
+            return get_data_from_url(url)
+        }
+        Some(path) => return get_data_from_file(path),
+        None => {}
+    }
+    parse_json(data.unwrap_or("{}")).context(format!(
         "Can't parse data args: \'{}\' into json",
         data.unwrap_or("")
     ))
 }
 
-pub fn editor(original: String) -> Result<Value> {
-    let data: Value = serde_json::from_str(original.as_str())?;
+// With --strict-json, duplicate keys are an error. serde_json already rejects trailing data.
+fn parse_json(content: &str) -> Result<Value> {
+    if STRICT_JSON.load(AtomicOrdering::Relaxed) {
+        Ok(serde_json::from_str::<StrictValue>(content)?.0)
+    } else {
+        Ok(from_str(content)?)
+    }
+}
+
+// A JSON value rejecting duplicate keys, where serde_json keeps the last value.
+// The errors tell the line and column of the duplicate.
+struct StrictValue(Value);
+
+impl<'de> Deserialize<'de> for StrictValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        deserializer.deserialize_any(StrictVisitor).map(StrictValue)
+    }
+}
+
+struct StrictVisitor;
+
+impl<'de> Visitor<'de> for StrictVisitor {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("a JSON value")
+    }
+
+    fn visit_bool<E>(self, v: bool) -> std::result::Result<Value, E> {
+        Ok(Value::Bool(v))
+    }
+
+    fn visit_i64<E>(self, v: i64) -> std::result::Result<Value, E> {
+        Ok(Value::from(v))
+    }
+
+    fn visit_u64<E>(self, v: u64) -> std::result::Result<Value, E> {
+        Ok(Value::from(v))
+    }
+
+    fn visit_f64<E>(self, v: f64) -> std::result::Result<Value, E> {
+        Ok(Value::from(v))
+    }
+
+    fn visit_str<E>(self, v: &str) -> std::result::Result<Value, E> {
+        Ok(Value::String(v.to_string()))
+    }
+
+    fn visit_unit<E>(self) -> std::result::Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> std::result::Result<Value, A::Error> {
+        let mut items = Vec::new();
+        while let Some(StrictValue(item)) = seq.next_element()? {
+            items.push(item);
+        }
+        Ok(Value::Array(items))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> std::result::Result<Value, A::Error> {
+        let mut object = serde_json::Map::new();
+        while let Some(key) = map.next_key::<String>()? {
+            if object.contains_key(&key) {
+                return Err(de::Error::custom(format!("duplicate key \"{}\"", key)));
+            }
+            let StrictValue(value) = map.next_value()?;
+            object.insert(key, value);
+        }
+        Ok(Value::Object(object))
+    }
+}
+
+// With a path, only this field of the resource is edited, and then merged back in the resource.
+pub fn editor(original: String, path: Option<&str>, yes: bool) -> Result<Value> {
+    let resource: Value = serde_json::from_str(original.as_str())?;
+    let pointer = path.map(field_pointer).unwrap_or_default();
+    let data = resource
+        .pointer(&pointer)
+        .cloned()
+        .ok_or_else(|| anyhow!("No field {} in the resource.", path.unwrap_or_default()))?;
 
     let file = Builder::new().suffix(".yml").tempfile()?;
     //the handler needs to be kept to reopen the file later.
@@ -113,9 +1114,22 @@ pub fn editor(original: String) -> Result<Value> {
     if data == new_data {
         println!("Edit cancelled, no changes made.");
         exit(2);
-    } else {
-        Ok(new_data)
     }
+
+    // a last look at what will be sent, a typo in the editor is easily missed.
+    if !yes {
+        eprintln!("{}", serde_yaml::to_string(&new_data)?);
+        if !confirm("Send these changes ?")? {
+            println!("Edit cancelled. Use --yes to skip the confirmation.");
+            exit(2);
+        }
+    }
+
+    let mut resource = resource;
+    if let Some(field) = resource.pointer_mut(&pointer) {
+        *field = new_data;
+    }
+    Ok(resource)
 }
 
 pub fn print_version(config: &Result<Config>) {
@@ -152,18 +1166,20 @@ pub fn print_version(config: &Result<Config>) {
 
 // use drogue's well known endpoint to retrieve endpoints.
 pub fn get_drogue_services_endpoints(url: Url) -> Result<(Url, Url)> {
-    let client = Client::new();
+    let client = http::client();
 
     let url = url.join(".well-known/drogue-endpoints")?;
 
     let res = client
-        .get(url)
+        .get(url.clone())
         .send()
-        .context("Can't retrieve drogue endpoints details")?;
+        .and_then(|r| r.error_for_status())
+        .context(format!("Couldn't reach drogue cloud discovery at {}", url))?;
 
-    let endpoints: Value = res
-        .json()
-        .context("Cannot deserialize drogue endpoints details")?;
+    let endpoints: Value = res.json().context(format!(
+        "{} is not a drogue cloud endpoint, cannot deserialize drogue endpoints details",
+        url
+    ))?;
 
     let sso = endpoints["issuer_url"]
         .as_str()
@@ -180,13 +1196,10 @@ pub fn get_drogue_services_endpoints(url: Url) -> Result<(Url, Url)> {
 }
 
 fn get_drogue_endpoints_authenticated(context: &Context) -> Result<Value> {
-    let client = Client::new();
+    let client = http::client();
     let url = format!("{}api/console/v1alpha1/info", &context.registry_url);
-    let res = client
-        .get(url)
-        .bearer_auth(&context.token.access_token().secret())
-        .send()
-        .context("Can't retrieve drogue services details")?;
+    let res =
+        http::send(context, client.get(url)).context("Can't retrieve drogue services details")?;
 
     res.json()
         .context("Cannot deserialize drogue endpoints details")
@@ -204,17 +1217,19 @@ pub fn get_drogue_websocket_endpoint(context: &Context) -> Result<Url> {
 // use keycloak's well known endpoint to retrieve endpoints.
 // http://keycloakhost:keycloakport/auth/realms/{realm}/.well-known/openid-configuration
 pub fn get_auth_and_tokens_endpoints(issuer_url: Url) -> Result<(Url, Url)> {
-    let client = Client::new();
+    let client = http::client();
 
     let url = issuer_url.join(".well-known/openid-configuration")?;
     let res = client
-        .get(url)
+        .get(url.clone())
         .send()
-        .context("Can't retrieve openid-connect endpoints details")?;
+        .and_then(|r| r.error_for_status())
+        .context(format!("Couldn't reach OIDC discovery at {}", url))?;
 
-    let endpoints: Value = res
-        .json()
-        .context("Cannot deserialize openid-connect endpoints details")?;
+    let endpoints: Value = res.json().context(format!(
+        "Cannot deserialize openid-connect endpoints details from {}",
+        url
+    ))?;
 
     let auth = endpoints["authorization_endpoint"]
         .as_str()
@@ -247,8 +1262,8 @@ pub fn log_level(matches: &ArgMatches) -> LevelFilter {
 }
 
 // use drogue's well known endpoint to retrieve version.
-fn get_drogue_services_version(url: &Url) -> Result<String> {
-    let client = Client::new();
+pub fn get_drogue_services_version(url: &Url) -> Result<String> {
+    let client = http::client();
 
     let url = url.join(".well-known/drogue-version")?;
 
@@ -269,29 +1284,280 @@ fn get_drogue_services_version(url: &Url) -> Result<String> {
 }
 
 pub fn get_data_from_file(path: &str) -> Result<Value> {
+    read_data_file(path, ENV_SUBST.load(AtomicOrdering::Relaxed))
+}
+
+// The data of the file as written, the variables are substituted when it is used.
+pub fn get_raw_data_from_file(path: &str) -> Result<Value> {
+    read_data_file(path, false)
+}
+
+// The variables are substituted in the parsed strings, so their values can't change the structure.
+fn read_data_file(path: &str, substitute: bool) -> Result<Value> {
+    let data = parse_data_file(path)?;
+    if substitute {
+        subst_strings(&data).context(format!("In file {}", path))
+    } else {
+        Ok(data)
+    }
+}
+
+fn parse_data_file(path: &str) -> Result<Value> {
     let contents = fs::read_to_string(path).context("Something went wrong reading the file")?;
 
-    serde_json::from_str(contents.as_str()).context("Invalid JSON in file")
+    // in strict mode, JSON is not parsed again as YAML, which would accept the duplicate keys.
+    let trimmed = contents.trim_start();
+    if STRICT_JSON.load(AtomicOrdering::Relaxed)
+        && (trimmed.starts_with('{') || trimmed.starts_with('['))
+    {
+        return parse_json(&contents).context(format!("Invalid JSON in file {}", path));
+    }
+
+    serde_json::from_str(contents.as_str())
+        .or_else(|_| serde_yaml::from_str(contents.as_str()))
+        .context("Invalid JSON or YAML in file")
+}
+
+// Substitute the variables in the strings of data read with get_raw_data_from_file.
+pub fn env_subst_value(value: &Value) -> Result<Value> {
+    if ENV_SUBST.load(AtomicOrdering::Relaxed) {
+        subst_strings(value)
+    } else {
+        Ok(value.clone())
+    }
+}
+
+fn subst_strings(value: &Value) -> Result<Value> {
+    Ok(match value {
+        Value::String(s) => Value::String(env_subst(s)?),
+        Value::Array(items) => Value::Array(
+            items
+                .iter()
+                .map(subst_strings)
+                .collect::<Result<Vec<Value>>>()?,
+        ),
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(k, v)| Ok((k.clone(), subst_strings(v)?)))
+                .collect::<Result<serde_json::Map<String, Value>>>()?,
+        ),
+        v => v.clone(),
+    })
+}
+
+pub fn set_env_subst(enabled: bool) {
+    ENV_SUBST.store(enabled, AtomicOrdering::Relaxed);
+}
+
+// The documents of a YAML stream, separated by `---`. A JSON file is a single document.
+pub fn get_documents_from_file(path: &str) -> Result<Vec<Value>> {
+    let contents = fs::read_to_string(path).context(format!("Cannot read {}", path))?;
+
+    let mut documents = Vec::new();
+    for (i, document) in serde_yaml::Deserializer::from_str(&contents).enumerate() {
+        let document = Value::deserialize(document).context(format!(
+            "Invalid document {} in {}",
+            i + 1,
+            path
+        ))?;
+        // empty documents, e.g. after a trailing ---, are skipped.
+        if !document.is_null() {
+            documents.push(env_subst_value(&document).context(format!("In file {}", path))?);
+        }
+    }
+    Ok(documents)
+}
+
+pub fn set_strict_json(strict: bool) {
+    STRICT_JSON.store(strict, AtomicOrdering::Relaxed);
+}
+
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, AtomicOrdering::Relaxed);
+}
+
+// Tell which app is used when it was not given, on stderr so pipelines are not affected.
+// It's only said once, a command may need the app several times.
+pub fn app_notice(notice: &str) {
+    if !QUIET.load(AtomicOrdering::Relaxed) && !APP_NOTICE.swap(true, AtomicOrdering::Relaxed) {
+        eprintln!("{}", notice);
+    }
+}
+
+// Expand ${VAR}, $VAR and ${VAR:-default} from the environment, $$ is a literal $.
+// Undefined variables are an error, unless they have a default.
+pub fn env_subst(content: &str) -> Result<String> {
+    let mut expanded = String::with_capacity(content.len());
+    let mut chars = content.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '$' {
+            expanded.push(c);
+            continue;
+        }
+        match chars.peek().copied() {
+            Some('$') => {
+                chars.next();
+                expanded.push('$');
+            }
+            Some('{') => {
+                chars.next();
+                let mut expression = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => expression.push(c),
+                        None => return Err(anyhow!("Missing }} after ${{{}", expression)),
+                    }
+                }
+                let (name, default) = match expression.split_once(":-") {
+                    Some((name, default)) => (name, Some(default)),
+                    None => (expression.as_str(), None),
+                };
+                expanded.push_str(&env_value(name, default)?);
+            }
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+                let mut name = String::new();
+                while let Some(c) = chars
+                    .peek()
+                    .copied()
+                    .filter(|c| c.is_ascii_alphanumeric() || *c == '_')
+                {
+                    name.push(c);
+                    chars.next();
+                }
+                expanded.push_str(&env_value(&name, None)?);
+            }
+            _ => expanded.push(c),
+        }
+    }
+    Ok(expanded)
+}
+
+// Like shells do, the default is also used for an empty variable.
+fn env_value(name: &str, default: Option<&str>) -> Result<String> {
+    match (std::env::var(name), default) {
+        (Ok(value), Some(default)) if value.is_empty() => Ok(default.to_string()),
+        (Ok(value), _) => Ok(value),
+        (Err(_), Some(default)) => Ok(default.to_string()),
+        (Err(_), None) => Err(anyhow!(
+            "Environment variable {} is not set. Use ${{{}:-default}} to give it a default value, or --no-env-subst.",
+            name,
+            name
+        )),
+    }
+}
+
+// Fetch JSON or YAML data, given as @https://... The request is not authenticated.
+fn get_data_from_url(url: &str) -> Result<Value> {
+    let url = Url::parse(url).context(format!("Invalid data url {}", url))?;
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err(anyhow!(
+            "Data can only be fetched from http(s) urls, not {}",
+            url
+        ));
+    }
+
+    let contents = http::client()
+        .get(url.clone())
+        .send()
+        .and_then(|r| r.error_for_status())
+        .and_then(|r| r.text())
+        .context(format!("Couldn't fetch data from {}", url))?;
+
+    from_str(&contents)
+        .or_else(|_| serde_yaml::from_str(&contents))
+        .context(format!(
+            "Data fetched from {} is neither JSON nor YAML",
+            url
+        ))
+}
+
+// Resources pending deletion, e.g. waiting for finalizers, carry a deletion timestamp.
+pub fn deletion_age(resource: &Value) -> Result<String> {
+    match resource["metadata"]["deletionTimestamp"].as_str() {
+        Some(deletion) => Ok(format!("deleted {} ago", age(deletion)?)),
+        None => Ok(String::new()),
+    }
 }
 
 pub fn age(str_timestamp: &str) -> Result<String> {
     let time = chrono::DateTime::parse_from_rfc3339(str_timestamp)?;
-    let age = Utc::now().naive_utc() - time.naive_utc();
+    Ok(humanize_age(Utc::now().naive_utc() - time.naive_utc()))
+}
+
+// The creation time as an age, unless the full timestamp is asked for.
+pub fn timestamp(str_timestamp: &str, full: bool) -> Result<String> {
+    if full {
+        Ok(str_timestamp.to_string())
+    } else {
+        age(str_timestamp)
+    }
+}
 
+// A duration the way kubectl shows ages : 3d, 5h, 2m.
+pub fn humanize_age(age: Duration) -> String {
     if age > Duration::days(7) {
-        Ok(format!("{}d", age.num_days()))
+        format!("{}d", age.num_days())
     } else if age > Duration::days(3) {
         let hours = age
             .checked_sub(&Duration::days(age.num_days()))
             .unwrap_or_else(|| Duration::hours(0));
-        Ok(format!("{}d{}h", age.num_days(), hours.num_hours()))
+        format!("{}d{}h", age.num_days(), hours.num_hours())
     } else if age > Duration::hours(2) {
-        Ok(format!("{}h", age.num_hours()))
+        format!("{}h", age.num_hours())
     } else if age > Duration::minutes(2) {
-        Ok(format!("{}m", age.num_minutes()))
+        format!("{}m", age.num_minutes())
     } else {
-        Ok(format!("{}s", age.num_seconds()))
+        format!("{}s", age.num_seconds().max(0))
+    }
+}
+
+// A point in time, given as an RFC 3339 timestamp or as a duration before now, such as 24h or 7d.
+pub fn parse_time(time: &str) -> Result<DateTime<Utc>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(time) {
+        return Ok(time.with_timezone(&Utc));
     }
+    let ago = parse_duration(time).context(format!(
+        "Invalid time '{}', expected a timestamp such as 2021-09-01T12:00:00Z or a duration such as 24h",
+        time
+    ))?;
+    Duration::from_std(ago)
+        .ok()
+        .and_then(|ago| Utc::now().checked_sub_signed(ago))
+        .ok_or_else(|| anyhow!("Invalid time '{}', too far in the past", time))
+}
+
+// parse a human friendly duration such as 500ms, 30s, 5m, 2h or 1d.
+// A bare number is interpreted as seconds.
+pub fn parse_duration(duration: &str) -> Result<StdDuration> {
+    let duration = duration.trim();
+    let split = duration
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(duration.len());
+    let (value, unit) = duration.split_at(split);
+
+    let value: u64 = value
+        .parse()
+        .context(format!("Invalid duration: \'{}\'", duration))?;
+
+    let seconds = match unit {
+        "ms" => return Ok(StdDuration::from_millis(value)),
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 60 * 60 * 24,
+        u => {
+            return Err(anyhow!(
+                "Invalid duration unit \'{}\', expected one of ms, s, m, h or d",
+                u
+            ))
+        }
+    };
+    value
+        .checked_mul(seconds)
+        .map(StdDuration::from_secs)
+        .ok_or_else(|| anyhow!("Duration too long: \'{}\'", duration))
 }
 
 pub fn print_endpoints(context: &Context, service: Option<&str>) -> Result<()> {
@@ -340,3 +1606,293 @@ fn deserialize_endpoint(details: &Value) -> (Option<String>, String) {
     let port = port.map_or("".to_string(), |p| format!(":{}", p));
     (host, port)
 }
+
+#[cfg(test)]
+mod util_test {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(
+            parse_duration("250ms").unwrap(),
+            StdDuration::from_millis(250)
+        );
+        assert_eq!(parse_duration("12").unwrap(), StdDuration::from_secs(12));
+        assert_eq!(parse_duration("3s").unwrap(), StdDuration::from_secs(3));
+        assert_eq!(parse_duration("2m").unwrap(), StdDuration::from_secs(120));
+        assert_eq!(parse_duration("1h").unwrap(), StdDuration::from_secs(3600));
+        assert_eq!(parse_duration("1d").unwrap(), StdDuration::from_secs(86400));
+        assert!(parse_duration("999999999999999999d").is_err());
+    }
+
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(
+            parse_time("2021-09-01T12:00:00Z").unwrap().to_rfc3339(),
+            "2021-09-01T12:00:00+00:00"
+        );
+        assert!(parse_time("1h").unwrap() < Utc::now());
+        assert!(parse_time("999999999999d").is_err());
+    }
+
+    #[test]
+    fn test_filter_since_until() {
+        let output = Output {
+            since: Some(parse_time("2021-09-01T00:00:00Z").unwrap()),
+            until: Some(parse_time("2021-09-30T00:00:00Z").unwrap()),
+            ..Default::default()
+        };
+        let list = vec![
+            serde_json::json!({"metadata": {"name": "a", "creationTimestamp": "2021-08-15T00:00:00Z"}}),
+            serde_json::json!({"metadata": {"name": "b", "creationTimestamp": "2021-09-15T00:00:00Z"}}),
+            serde_json::json!({"metadata": {"name": "c", "creationTimestamp": "2021-10-15T00:00:00Z"}}),
+            serde_json::json!({"metadata": {"name": "d"}}),
+        ];
+        let names: Vec<String> = output.filter(list).iter().map(resource_name).collect();
+        assert_eq!(names, vec!["b"]);
+    }
+
+    #[test]
+    fn test_parse_invalid_duration() {
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("ten").is_err());
+        assert!(parse_duration("5y").is_err());
+    }
+
+    #[test]
+    fn test_result_no_content() {
+        assert_eq!(
+            result_message("", "Device foo", Verbs::delete),
+            Some("Device foo deleted.".to_string())
+        );
+        assert_eq!(
+            result_message("", "Device foo", Verbs::get),
+            Some("Device foo has no content.".to_string())
+        );
+    }
+
+    #[test]
+    fn test_result_text_body() {
+        assert_eq!(
+            result_message("scheduled for deletion", "Device foo", Verbs::delete),
+            Some("Device foo deleted.\nscheduled for deletion".to_string())
+        );
+        assert_eq!(result_message("not json", "Device foo", Verbs::get), None);
+        assert_eq!(
+            result_message(r#"{"metadata":{"name":"foo"}}"#, "Device foo", Verbs::edit),
+            Some("Device foo updated.".to_string())
+        );
+    }
+
+    #[test]
+    fn test_result_status() {
+        assert_eq!(expected_status(Verbs::create), Some(StatusCode::CREATED));
+        assert_eq!(expected_status(Verbs::delete), Some(StatusCode::NO_CONTENT));
+        assert_eq!(expected_status(Verbs::get), Some(StatusCode::OK));
+        assert_eq!(expected_status(Verbs::edit), Some(StatusCode::NO_CONTENT));
+    }
+
+    #[test]
+    fn test_expand_template() {
+        let device = serde_json::json!({"metadata": {"name": "foo", "labels": {"env": "prod"}}});
+        assert_eq!(expand_template("{name}.json", &device).unwrap(), "foo.json");
+        assert_eq!(
+            expand_template("{labels.env}-{name}.yaml", &device).unwrap(),
+            "prod-foo.yaml"
+        );
+        assert!(expand_template("{uid}.json", &device).is_err());
+        assert!(expand_template("{name.json", &device).is_err());
+    }
+
+    #[test]
+    fn test_render_template() {
+        let device = serde_json::json!({"metadata": {"name": "foo"}, "spec": {"alias": ["bar"]}});
+        assert_eq!(
+            render_template("{metadata.name}: { spec.alias.0 } {status.state}", &device).unwrap(),
+            "foo: bar "
+        );
+        assert!(render_template("{metadata.name", &device).is_err());
+    }
+
+    #[test]
+    fn test_set_resource_version() {
+        let mut device = serde_json::json!({"spec": {}});
+        set_resource_version(&mut device, "42").unwrap();
+        assert_eq!(device["metadata"]["resourceVersion"], "42");
+
+        assert!(set_resource_version(&mut serde_json::json!([]), "42").is_err());
+        assert!(set_resource_version(&mut serde_json::json!({"metadata": 1}), "42").is_err());
+    }
+
+    #[test]
+    fn test_bulk_exit_code() {
+        assert_eq!(bulk_exit_code(3, 3), 0);
+        assert_eq!(bulk_exit_code(0, 0), 0);
+        assert_eq!(bulk_exit_code(1, 3), PARTIAL_FAILURE_EXIT_CODE);
+        assert_eq!(bulk_exit_code(0, 3), TOTAL_FAILURE_EXIT_CODE);
+    }
+
+    #[test]
+    fn test_humanize_age() {
+        assert_eq!(humanize_age(Duration::seconds(42)), "42s");
+        assert_eq!(humanize_age(Duration::minutes(5)), "5m");
+        assert_eq!(humanize_age(Duration::hours(5)), "5h");
+        assert_eq!(humanize_age(Duration::hours(4 * 24 + 3)), "4d3h");
+        assert_eq!(humanize_age(Duration::days(30)), "30d");
+        // clock skew between the server and the client.
+        assert_eq!(humanize_age(Duration::seconds(-3)), "0s");
+    }
+
+    #[test]
+    fn test_sort_by_missing_last() {
+        let output = Output {
+            sort_by: Some("metadata.name"),
+            ..Default::default()
+        };
+        let mut list = vec![
+            serde_json::json!({"metadata": {"name": "b"}}),
+            serde_json::json!({"metadata": {}}),
+            serde_json::json!({"metadata": {"name": "a"}}),
+        ];
+        output.sort(&mut list);
+
+        assert_eq!(list[0]["metadata"]["name"], "a");
+        assert_eq!(list[1]["metadata"]["name"], "b");
+        assert_eq!(list[2]["metadata"]["name"], Value::Null);
+    }
+
+    #[test]
+    fn test_env_subst() {
+        std::env::set_var("DRG_TEST_SECRET", "s3cr3t");
+        std::env::remove_var("DRG_TEST_MISSING");
+
+        assert_eq!(
+            env_subst(
+                r#"{"pass": "${DRG_TEST_SECRET}", "user": "$DRG_TEST_SECRET", "price": "5$"}"#
+            )
+            .unwrap(),
+            r#"{"pass": "s3cr3t", "user": "s3cr3t", "price": "5$"}"#
+        );
+        assert_eq!(
+            env_subst("${DRG_TEST_MISSING:-default}").unwrap(),
+            "default"
+        );
+        assert!(env_subst("$DRG_TEST_MISSING").is_err());
+        assert_eq!(
+            env_subst_value(&serde_json::json!({"credentials": [{"pass": "${DRG_TEST_SECRET}"}]}))
+                .unwrap(),
+            serde_json::json!({"credentials": [{"pass": "s3cr3t"}]})
+        );
+        assert!(env_subst("${DRG_TEST_SECRET").is_err());
+    }
+
+    #[test]
+    fn test_env_subst_escape() {
+        std::env::remove_var("DRG_TEST_WORD");
+        assert_eq!(env_subst("pa$$DRG_TEST_WORD").unwrap(), "pa$DRG_TEST_WORD");
+        assert_eq!(env_subst("$${DRG_TEST_WORD}").unwrap(), "${DRG_TEST_WORD}");
+    }
+
+    #[test]
+    fn test_env_subst_quotes() {
+        std::env::set_var("DRG_TEST_QUOTED", r#"a"b\c", "admin": "true"#);
+
+        let file = Builder::new().suffix(".json").tempfile().unwrap();
+        fs::write(file.path(), r#"{"pass": "${DRG_TEST_QUOTED}"}"#).unwrap();
+        let data = read_data_file(file.path().to_str().unwrap(), true).unwrap();
+
+        assert_eq!(
+            data,
+            serde_json::json!({"pass": r#"a"b\c", "admin": "true"#})
+        );
+    }
+
+    #[test]
+    fn test_fields_missing_empty() {
+        let device = serde_json::json!({"metadata": {"name": "foo", "generation": 2}});
+
+        assert_eq!(
+            fields(
+                &device,
+                &["metadata.name", "metadata.generation", "spec.foo"]
+            ),
+            vec!["foo", "2", ""]
+        );
+    }
+
+    #[test]
+    fn test_env_output() {
+        let output = Output {
+            format: Some(Outputs::env),
+            columns: vec![
+                "metadata.name",
+                "spec.gatewaySelector.matchNames",
+                "spec.foo",
+            ],
+            label_columns: vec!["owner"],
+            ..Default::default()
+        };
+        let device = serde_json::json!({
+            "metadata": {"name": "foo", "application": "app", "labels": {"owner": "it's me"}},
+            "spec": {"gatewaySelector": {"matchNames": ["gw1", "gw2"]}},
+        });
+
+        assert_eq!(
+            output.env(&device),
+            "DEVICE_NAME='foo'\nDEVICE_MATCH_NAMES='gw1,gw2'\nDEVICE_LABEL_OWNER='it'\\''s me'"
+        );
+        assert_eq!(
+            env_name("drg_", "creationTimestamp"),
+            "DRG_CREATION_TIMESTAMP"
+        );
+    }
+
+    #[test]
+    fn test_fuzzy_match() {
+        assert!(fuzzy_match("temperature-sensor-1", "tsens1"));
+        assert!(fuzzy_match("Gateway", "gw"));
+        assert!(!fuzzy_match("gateway", "wg"));
+        assert!(fuzzy_match("anything", ""));
+    }
+
+    #[test]
+    fn test_strict_json() {
+        let value =
+            serde_json::from_str::<StrictValue>(r#"{"a": [1, 2.5, null], "b": {"c": true}}"#)
+                .unwrap();
+        assert_eq!(
+            value.0,
+            serde_json::json!({"a": [1, 2.5, null], "b": {"c": true}})
+        );
+
+        let error = serde_json::from_str::<StrictValue>("{\n  \"a\": 1,\n  \"a\": 2\n}")
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(error.contains("duplicate key \"a\""), "{}", error);
+        assert!(error.contains("line 3"), "{}", error);
+        assert!(serde_json::from_str::<StrictValue>("{} {}").is_err());
+    }
+
+    #[test]
+    fn test_metadata_changes() {
+        let changes = parse_metadata_changes(&["env=prod", "region-", "url=a=b"]).unwrap();
+        assert_eq!(
+            changes,
+            vec![
+                ("env".to_string(), Some("prod".to_string())),
+                ("region".to_string(), None),
+                ("url".to_string(), Some("a=b".to_string())),
+            ]
+        );
+        assert!(parse_metadata_changes(&["env"]).is_err());
+        assert!(parse_metadata_changes(&["=prod"]).is_err());
+
+        let mut labels = serde_json::json!({"region": "us", "team": "a"});
+        apply_metadata_changes(&mut labels, &changes);
+        assert_eq!(
+            labels,
+            serde_json::json!({"env": "prod", "team": "a", "url": "a=b"})
+        );
+    }
+}
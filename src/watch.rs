@@ -0,0 +1,117 @@
+use crate::config::Context;
+use crate::{http, util};
+
+use anyhow::{Context as AnyhowContext, Result};
+use reqwest::blocking::{Client, Response};
+use reqwest::header::{ACCEPT, CONTENT_TYPE};
+use reqwest::StatusCode;
+use serde_json::Value;
+use std::io::{BufRead, BufReader};
+use std::thread;
+use std::time::Duration;
+
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+// Print the resource, or only its status, each time it changes.
+// The server is asked for a server-sent events stream, if it replies with a
+// plain JSON document instead the resource is polled.
+pub fn watch(config: &Context, url: &str, interval: Duration, status_only: bool) -> Result<()> {
+    let mut changes = Changes {
+        status_only,
+        last: None,
+    };
+
+    // the stream stays open as long as the server sends events.
+    let client = http::builder().timeout(None).build()?;
+
+    let req = client.get(url).header(ACCEPT, "text/event-stream");
+    let res = http::send_stream(config, req).context("Can't watch resource.")?;
+
+    let is_event_stream = res
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.starts_with("text/event-stream"))
+        .unwrap_or(false);
+
+    match res.status() {
+        StatusCode::OK if is_event_stream => read_events(res, &mut changes),
+        StatusCode::OK => {
+            log::info!(
+                "Server does not support watch, polling every {:?}",
+                interval
+            );
+            changes.print(&res.text()?);
+            poll(config, &client, url, interval, &mut changes)
+        }
+        r => util::exit_with_code(r),
+    }
+}
+
+// Remembers what was printed last, to only print changes.
+struct Changes {
+    status_only: bool,
+    last: Option<String>,
+}
+
+impl Changes {
+    fn print(&mut self, body: &str) {
+        let shown = if self.status_only {
+            serde_json::from_str::<Value>(body)
+                .map(|r| r["status"].to_string())
+                .unwrap_or_else(|_| body.to_string())
+        } else {
+            body.to_string()
+        };
+
+        if self.last.as_ref() != Some(&shown) {
+            util::show_json(&shown);
+            self.last = Some(shown);
+        }
+    }
+}
+
+fn read_events(res: Response, changes: &mut Changes) -> Result<()> {
+    let mut data: Vec<String> = Vec::new();
+
+    for line in BufReader::new(res).lines() {
+        let line = line.context("Error reading the event stream")?;
+
+        if line.is_empty() {
+            // a blank line dispatches the event
+            if !data.is_empty() {
+                changes.print(&data.join("\n"));
+                data.clear();
+            }
+        } else if let Some(d) = line.strip_prefix("data:") {
+            data.push(d.trim_start().to_string());
+        }
+        // other fields (event, id, retry) and comments are ignored
+    }
+
+    log::info!("The server closed the event stream.");
+    Ok(())
+}
+
+fn poll(
+    config: &Context,
+    client: &Client,
+    url: &str,
+    interval: Duration,
+    changes: &mut Changes,
+) -> Result<()> {
+    loop {
+        thread::sleep(interval);
+
+        let res = http::send(config, client.get(url)).context("Can't watch resource.")?;
+
+        match res.status() {
+            StatusCode::OK => changes.print(&res.text()?),
+            StatusCode::NOT_FOUND => {
+                println!("Resource deleted.");
+                return Ok(());
+            }
+            r => util::exit_with_code(r),
+        }
+    }
+}